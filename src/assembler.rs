@@ -1,120 +1,741 @@
 // takes in XXX.asm , outputs XXX.hack and if XXX.hack already exists, override the existing one
+// the assembler itself works on source text in memory, reading .asm files and writing
+// output/XXX.hack is a thin layer on top for the command line
 
 use std::{
-    fs::{create_dir_all, File},
-    io::{self, BufRead, Write}, path::Path,
+    cell::OnceCell,
+    collections::{BTreeMap, HashMap},
+    fs::{self, create_dir_all, File},
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
 };
 
 use crate::{
-    parser::{InstructionVariant, Parser},
+    error::{AssembleError, ErrorKind, ErrorList, IncludeSite, Related},
+    expression::{self, Expression},
+    interner::Name,
+    lexer::{Lexer, TokenKind},
+    lint::{self, LintLevel, LintLevels, Warning},
+    macros::{ExpandedLine, MAX_EXPANDED_LINES, MAX_EXPANSION_DEPTH},
+    output::{self, ListingLine},
+    parser::{self, Condition, Instruction, InstructionVariant, Parser, ROM_SIZE},
     symbol_handler::SymbolTable,
-    translator::{translate_a_instruction, translate_c_instruction},
+    translator::translate_c_instruction,
 };
 
-fn clean_line(line: &str) -> Option<&str> {
-    let trimmed = line.trim();
-    if trimmed.is_empty() {
-        return None;
-    }
-
-    let no_comment = trimmed.split("//").next().unwrap().trim();
-    if no_comment.is_empty() {
-        return None;
-    }
-
-    Some(no_comment)
-}
-
-
 pub fn extract_file_name(file_name_or_path: &str ) -> String {
     file_name_or_path
         .split('/')
         .collect::<Vec<&str>>()
         .last()
-        .and_then(|s| s.split('.').collect::<Vec<&str>>().get(0).copied())
+        .and_then(|s| s.split('.').collect::<Vec<&str>>().first().copied())
         .unwrap_or("")
         .to_string()
 }
 
-pub fn assemble(file_name_or_path: &str) -> io::Result<bool> {
-    let file = File::open(file_name_or_path)?;
-    let reader = io::BufReader::new(file);
-    let lines: Vec<String> = reader.lines().collect::<Result<Vec<_>, _>>()?;
-    let mut parser = Parser::new(); // initialize parser
-    let mut symbol_table = SymbolTable::new(); // initialize symbol table
+// which comments the assembler understands
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CommentStyle {
+    // only `// ...` line comments, like the Nand2Tetris assembler
+    Line,
+    // `// ...` and `/* ... */` comments, which may span lines
+    #[default]
+    Block,
+}
+
+// knobs for a single assembler run
+#[derive(Debug, Clone, Default)]
+pub struct AssembleOptions {
+    // stop collecting errors once this many have been found, None means report them all
+    pub max_errors: Option<usize>,
+    // which lints are allowed, warned about or denied
+    pub lints: LintLevels,
+    // accept A-instruction constants above 32767 and emit them as raw 16-bit words
+    pub allow_raw_words: bool,
+    // directories searched for `.include` files not found next to the including file
+    pub include_paths: Vec<PathBuf>,
+    // read `goto LABEL`, `inc x` and the other pseudo-instructions
+    pub pseudo_instructions: bool,
+    // also build Program::listing and write output/XXX.lst, every instruction and its word
+    pub listing: bool,
+    // constants defined before the first line is read, `-D NAME=value` on the command line
+    pub defines: Vec<(String, u16)>,
+    // whether `/* ... */` comments are accepted
+    pub comment_style: CommentStyle,
+}
+
+// reads `NAME=value` or `NAME` of `-D NAME=value`, a name without a value is 1
+pub fn parse_define(text: &str) -> Result<(String, u16), String> {
+    let (name, value) = text.split_once('=').unwrap_or((text, "1"));
+    let tokens: Vec<_> = Lexer::new(name).collect();
+    if !matches!(tokens.as_slice(), [token] if token.kind == TokenKind::Identifier && token.text == name) {
+        return Err(format!("Invalid name in -D {}: expected a symbol name such as DEBUG", text));
+    }
+    match expression::parse_constant(value) {
+        Ok(value) if value <= 32767 => Ok((name.to_string(), value as u16)),
+        _ => Err(format!("Invalid value in -D {}: expected a number from 0 to 32767", text)),
+    }
+}
 
+// what a source assembled into
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    // the machine code, one word per ROM address
+    pub machine_code: Vec<u16>,
+    // the labels the source defined, with the ROM address each one names
+    pub labels: BTreeMap<String, u16>,
+    // the constants defined with `.equ` or `#define`, with their values
+    pub constants: BTreeMap<String, u16>,
+    // the variables the source used, with the RAM address each one was given
+    pub variables: BTreeMap<String, u16>,
+    // lints that were warned about, denied lints are errors instead
+    pub warnings: Vec<Warning>,
+    // every instruction after expansion, with its address and word, empty unless
+    // AssembleOptions::listing is set
+    pub listing: Vec<ListingLine>,
+}
+
+impl Program {
+    // the machine code as the contents of a .hack file
+    pub fn hack_text(&self) -> String {
+        output::hack_text(&self.machine_code)
+    }
+
+    // the listing as the contents of a .lst file
+    pub fn listing_text(&self) -> String {
+        output::listing_text(&self.listing)
+    }
+}
+
+// the file name diagnostics use for sources that did not come from a file
+pub const INPUT_NAME: &str = "<input>";
+
+pub fn assemble(file_name_or_path: &str) -> Result<Vec<Warning>, Vec<AssembleError>> {
+    assemble_with_options(file_name_or_path, &AssembleOptions::default())
+}
+
+// assembles the file and writes output/XXX.hack
+// nothing is written to output/ unless the file assembled cleanly
+// returns the warnings for the file, denied lints are returned as errors
+pub fn assemble_with_options(
+    file_name_or_path: &str,
+    options: &AssembleOptions,
+) -> Result<Vec<Warning>, Vec<AssembleError>> {
+    let io_error = |error: io::Error| vec![AssembleError::io(file_name_or_path, &error)];
+    let source = fs::read_to_string(file_name_or_path).map_err(io_error)?;
+    let program = assemble_str_with_options(file_name_or_path, &source, options)?;
+    // I/O
+    // create directory if it doesnt exist
+    let dir = Path::new("output");
+    if !dir.exists(){
+        create_dir_all(dir).map_err(io_error)?;
+    }
+    // create file_path
+    let file_path = dir.join(format!("{}.hack",extract_file_name(file_name_or_path)));
+    let mut file = File::create(file_path).map_err(io_error)?;
+    file.write_all(program.hack_text().as_bytes()).map_err(io_error)?;
+    if options.listing {
+        let listing_path = dir.join(format!("{}.lst", extract_file_name(file_name_or_path)));
+        fs::write(listing_path, program.listing_text()).map_err(io_error)?;
+    }
+
+    Ok(program.warnings)
+}
+
+pub fn assemble_str(source: &str) -> Result<Program, Vec<AssembleError>> {
+    assemble_str_with_options(INPUT_NAME, source, &AssembleOptions::default())
+}
+
+pub fn assemble_reader(reader: impl BufRead) -> Result<Program, Vec<AssembleError>> {
+    assemble_reader_with_options(INPUT_NAME, reader, &AssembleOptions::default())
+}
+
+// reads everything from reader and assembles it, `name` is the file diagnostics point at
+pub fn assemble_reader_with_options(
+    name: &str,
+    mut reader: impl BufRead,
+    options: &AssembleOptions,
+) -> Result<Program, Vec<AssembleError>> {
+    let mut source = String::new();
+    reader
+        .read_to_string(&mut source)
+        .map_err(|error| vec![AssembleError::io(name, &error)])?;
+    assemble_str_with_options(name, &source, options)
+}
+
+// assembles the whole source, collecting every error instead of stopping at the first one
+// `name` is the file diagnostics point at, only `.include` reads from the file system,
+// relative to the directory of `name` or the current directory for `<input>`
+pub fn assemble_str_with_options(
+    name: &str,
+    source: &str,
+    options: &AssembleOptions,
+) -> Result<Program, Vec<AssembleError>> {
+    let mut parser = Parser::for_file(name); // initialize parser
+    parser.allow_raw_words = options.allow_raw_words;
+    parser.pseudo_instructions = options.pseudo_instructions;
+    parser.block_comments = options.comment_style == CommentStyle::Block;
+    let mut first_pass = FirstPass {
+        parser,
+        symbol_table: SymbolTable::new(), // initialize symbol table
+        errors: ErrorList::new(options.max_errors),
+        instructions: Vec::new(),
+        definition_sites: HashMap::new(),
+        rom_full: false,
+        sources: Sources::new(source),
+        include_stack: vec![canonical(Path::new(name))],
+        include_paths: &options.include_paths,
+        expansions: 0,
+        expansion_depth: 0,
+        too_deep: false,
+        expanded_lines: 0,
+        too_large: false,
+    };
+    for (name, value) in &options.defines {
+        if let Err(error) = first_pass.symbol_table.add_constant(&Name::from(name.as_str()), *value) {
+            let note = format!("`{}` was defined with -D {}={}", name, name, value);
+            first_pass.errors.push(error.with_related(Related::note(&note)));
+        }
+    }
     // first pass -> maps labels only to the symbol table
-    for line in &lines {
-        if let Some(cleaned) = clean_line(line) {
-            let parsed_instruction = parser.parse(cleaned.to_string());
-            if let InstructionVariant::Symbol {
-                symbol,
-                is_variable,
-            } = parsed_instruction.variant
-            {
-                if !is_variable {
-                    // the symbol here is not a variable, it is a LABEL
-                    // we match the LABEL to the memory address on the next unstruction
-                    symbol_table.add_symbol(&symbol, parsed_instruction.line_number, is_variable);
+    first_pass.read_file(source);
+    let FirstPass {
+        parser,
+        mut symbol_table,
+        mut errors,
+        instructions,
+        sources,
+        ..
+    } = first_pass;
+    // the machine code, one word per ROM address
+    let mut image: Vec<u16> = Vec::with_capacity(instructions.len());
+    // only built when it is asked for, it copies the text of every instruction
+    let mut listing: Vec<ListingLine> = Vec::new();
+    let jumps_next = parser::jumps_next(&instructions);
+    // second pass ->
+    for (index, parsed_instruction) in instructions.iter().enumerate() {
+        // the first pass may have filled the list with errors further down the program
+        if !errors.accepts(index) {
+            return Err(errors.into_vec());
+        }
+        errors.set_position(index);
+        if let Some(error) = undefined_jump_target(parsed_instruction, jumps_next[index], &symbol_table) {
+            errors.push(locate(error, parsed_instruction, &sources));
+            continue;
+        }
+        match translate(parsed_instruction, &mut symbol_table, parser.max_constant()) {
+            Ok(word) => {
+                image.extend(word);
+                if options.listing {
+                    listing.push(listing_line(parsed_instruction, word, &sources));
+                }
+            }
+            Err(translate_errors) => {
+                for error in translate_errors {
+                    errors.push(locate(error, parsed_instruction, &sources));
                 }
             }
         }
     }
-    parser.clear();
-    let mut output_string = String::new();
-    // second pass ->
-    for line in &lines {
-        if let Some(cleaned) = clean_line(line) {
-            let parsed_instruction = parser.parse(cleaned.to_string());
-
-            match parsed_instruction.variant {
-                InstructionVariant::Symbol {
-                    symbol,
-                    is_variable,
-                } => {
-                    if is_variable {
-                        // the symbol here is a variable
-                        // check if the variable is in memory
-                        symbol_table.add_symbol(
-                            &symbol,
-                            symbol_table.get_next_free_address(),
-                            is_variable,
-                        );
-                        let memory_address = symbol_table.get_memory_address(&symbol);
-                        output_string.push_str(&translate_a_instruction(&memory_address));
-                        output_string.push('\n');
+    if !errors.is_empty() {
+        return Err(errors.into_vec());
+    }
+    // lints only look at files that assembled, a denied lint stops the file like any error
+    let (denied, warnings): (Vec<Warning>, Vec<Warning>) = lint::check(&instructions, &jumps_next, &options.lints, &options.defines)
+        .into_iter()
+        .partition(|warning| options.lints.level(warning.lint) == LintLevel::Deny);
+    for warning in denied {
+        errors.push(warning.into_error());
+    }
+    if !errors.is_empty() {
+        return Err(errors.into_vec());
+    }
+    Ok(Program {
+        machine_code: image,
+        labels: symbol_table.labels(),
+        constants: symbol_table.constants(),
+        variables: symbol_table.variables(),
+        warnings,
+        listing,
+    })
+}
+
+// an instruction and the word it assembled into, for the listing
+fn listing_line(instruction: &Instruction, word: Option<u16>, sources: &Sources) -> ListingLine {
+    // the innermost expansion, an `.include` is not shown
+    let expanded_from = instruction
+        .included_from
+        .first()
+        .filter(|site| site.macro_name.is_some())
+        .map(|site| strip_comment(sources.line(&site.file, site.line)).trim().to_string());
+    ListingLine {
+        address: instruction.rom_address,
+        word,
+        text: instruction.rep(),
+        file: Rc::clone(&instruction.file),
+        line: instruction.line_number,
+        expanded_from,
+    }
+}
+
+// the line up to a `//` comment
+fn strip_comment(line: &str) -> &str {
+    line.split_once("//").map_or(line, |(code, _)| code)
+}
+
+// the text of every file the instructions came from
+struct Sources<'a> {
+    // the source passed to the assembler, borrowed rather than copied
+    main: &'a str,
+    main_lines: LineIndex,
+    // files read for `.include`, by the name their instructions carry
+    included: HashMap<Rc<str>, (Rc<str>, LineIndex)>,
+}
+
+// where every line of a file starts, found the first time a line of the file is needed,
+// most files never need one as only diagnostics and the listing quote source lines
+#[derive(Default)]
+struct LineIndex {
+    starts: OnceCell<Vec<usize>>,
+}
+
+impl LineIndex {
+    // the 1-based line of source, the same line `source.lines()` would give
+    fn line<'s>(&self, source: &'s str, line_number: usize) -> &'s str {
+        let starts = self.starts.get_or_init(|| {
+            let newlines = source.match_indices('\n').map(|(offset, _)| offset + 1);
+            std::iter::once(0).chain(newlines).collect()
+        });
+        let Some(&start) = starts.get(line_number.wrapping_sub(1)) else {
+            return "";
+        };
+        let line = source[start..].split('\n').next().unwrap_or("");
+        line.strip_suffix('\r').unwrap_or(line)
+    }
+}
+
+impl<'a> Sources<'a> {
+    fn new(main: &'a str) -> Self {
+        Self {
+            main,
+            main_lines: LineIndex::default(),
+            included: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, file: Rc<str>, source: Rc<str>) {
+        self.included.insert(file, (source, LineIndex::default()));
+    }
+
+    // the 1-based line of the file an instruction came from
+    fn line(&self, file: &str, line_number: usize) -> &str {
+        match self.included.get(file) {
+            Some((source, lines)) => lines.line(source, line_number),
+            None => self.main_lines.line(self.main, line_number),
+        }
+    }
+}
+
+// the state of the first pass, shared by the main file and every file it includes
+struct FirstPass<'a> {
+    parser: Parser,
+    symbol_table: SymbolTable,
+    errors: ErrorList,
+    // instructions are parsed once, each one remembers the source line it came from
+    instructions: Vec<Instruction>,
+    // where each label or constant was first defined, as an index into instructions
+    definition_sites: HashMap<Name, usize>,
+    rom_full: bool,
+    sources: Sources<'a>,
+    // the files being read, outermost first, a file already on the stack is an include cycle
+    include_stack: Vec<PathBuf>,
+    include_paths: &'a [PathBuf],
+    // macro invocations expanded so far, numbers the local labels of each expansion
+    expansions: usize,
+    // invocations inside macro bodies being expanded right now
+    expansion_depth: usize,
+    // the expansion limit was hit, the expansions still open are abandoned
+    too_deep: bool,
+    // lines added by macro expansions so far
+    expanded_lines: usize,
+    // MAX_EXPANDED_LINES was hit, nothing else is expanded
+    too_large: bool,
+}
+
+impl FirstPass<'_> {
+    // parses every line of the file the parser is in, reading included files where they appear
+    fn read_file(&mut self, source: &str) {
+        // reads on past --max-errors, an error in the second pass needs every label
+        for line in source.lines() {
+            self.read_line(line, None);
+        }
+        if let Some(error) = self.parser.unterminated_comment() {
+            self.errors.push(error);
+        }
+        if let Some(error) = self.parser.unterminated_macro() {
+            self.errors.push(error);
+        }
+        if let Some(error) = self.parser.unterminated_conditional() {
+            self.errors.push(error);
+        }
+    }
+
+    // parses a line, `expanded` is set for a line of a macro body with the arguments substituted,
+    // its columns are moved back to where they were written in the body
+    fn read_line(&mut self, line: &str, expanded: Option<&ExpandedLine>) {
+        // errors on this line come before those of the next instruction
+        self.errors.set_position(self.instructions.len());
+        let mut parsed = self.parser.parse_line(line);
+        if let Some(expanded) = expanded {
+            match &mut parsed {
+                Ok(Some(parsed_instruction)) => {
+                    let span = &mut parsed_instruction.span;
+                    (span.start, span.end) = (expanded.original_column(span.start), expanded.original_column(span.end));
+                }
+                Ok(None) => {}
+                Err(parse_errors) => {
+                    for error in parse_errors {
+                        error.column = expanded.original_column(error.column);
                     }
                 }
-                InstructionVariant::A(num_string) => match num_string.parse::<u16>() {
-                    Ok(num) => {
-                        output_string.push_str(&translate_a_instruction(&num));
-                        output_string.push('\n');
+            }
+        }
+        let parsed_instruction = match parsed {
+            Ok(Some(parsed_instruction)) => parsed_instruction,
+            Ok(None) => return,
+            Err(parse_errors) => {
+                for error in parse_errors {
+                    self.errors.push(error);
+                }
+                return;
+            }
+        };
+        match &parsed_instruction.variant {
+            InstructionVariant::Include { path } => self.include(path, &parsed_instruction),
+            InstructionVariant::Invocation { name, args } => self.expand(name, args, &parsed_instruction),
+            InstructionVariant::Pseudo { name, lines, .. } => self.expand_pseudo(name, lines, &parsed_instruction),
+            InstructionVariant::If(condition) => self.begin_conditional(condition, &parsed_instruction),
+            _ => self.define(parsed_instruction),
+        }
+    }
+
+    // decides which branch of an `.if` block is assembled, only symbols defined above it
+    // count, labels further down the file and variables do not have a value yet
+    fn begin_conditional(&mut self, condition: &Condition, instruction: &Instruction) {
+        let taken = match condition {
+            Condition::Defined(name) => Ok(self.symbol_table.contains_symbol(name)),
+            Condition::NotDefined(name) => Ok(!self.symbol_table.contains_symbol(name)),
+            Condition::Expression(expression) => expression
+                .evaluate(&mut |symbol| self.symbol_table.get_memory_address(symbol))
+                .map(|value| value != 0)
+                .map_err(|error| match error.kind {
+                    ErrorKind::UnknownSymbol => {
+                        error.with_related(Related::note("conditions can only use symbols defined above them"))
                     }
-                    Err(_) => panic!("Invalid String to number conversion"),
-                },
-                InstructionVariant::C { comp, dest, jmp } => {
-                    output_string.push_str(&translate_c_instruction(&dest, &comp, &jmp));
-                    output_string.push('\n');
+                    _ => error,
+                }),
+        };
+        let taken = match taken {
+            Ok(taken) => Some(taken),
+            Err(error) => {
+                self.errors.push(locate(error, instruction, &self.sources));
+                None
+            }
+        };
+        self.parser.begin_conditional(taken, instruction);
+    }
+
+    // parses the body of the macro an instruction invokes in its place
+    fn expand(&mut self, name: &str, args: &[String], instruction: &Instruction) {
+        // nothing more could fit, the error has been reported already
+        if self.rom_full || self.too_large {
+            return;
+        }
+        if self.expansion_depth == MAX_EXPANSION_DEPTH {
+            self.errors.push(expansion_error(ErrorKind::MacroRecursion, name, instruction, &self.sources));
+            self.too_deep = true;
+            return;
+        }
+        // the parser only produces invocations of macros it has seen defined
+        let Some(definition) = self.parser.macro_definition(name) else {
+            return;
+        };
+        self.expansions += 1;
+        let site = expansion_site(name, instruction);
+        let state = self.parser.enter_file(&definition.file, definition.line_number + 1, site);
+        self.expansion_depth += 1;
+        for line in definition.expand(args, self.expansions) {
+            if self.too_deep || self.too_large || self.rom_full {
+                break;
+            }
+            self.expanded_lines += 1;
+            if self.expanded_lines > MAX_EXPANDED_LINES {
+                self.errors.push(expansion_error(ErrorKind::ExpansionTooLarge, name, instruction, &self.sources));
+                self.too_large = true;
+                break;
+            }
+            self.read_line(&line.text, Some(&line));
+        }
+        if let Some(error) = self.parser.unterminated_comment() {
+            self.errors.push(error);
+        }
+        if let Some(error) = self.parser.unterminated_conditional() {
+            self.errors.push(error);
+        }
+        self.expansion_depth -= 1;
+        self.too_deep &= self.expansion_depth > 0;
+        self.parser.leave_file(state);
+    }
+
+    // parses the real instructions a pseudo-instruction stands for, all on its line
+    fn expand_pseudo(&mut self, name: &str, lines: &[ExpandedLine], instruction: &Instruction) {
+        let site = expansion_site(name, instruction);
+        let state = self.parser.enter_file(&instruction.file, instruction.line_number, site);
+        for line in lines {
+            self.parser.line_number = instruction.line_number;
+            self.read_line(&line.text, Some(line));
+        }
+        if let Some(error) = self.parser.unterminated_conditional() {
+            self.errors.push(error);
+        }
+        self.parser.leave_file(state);
+    }
+
+    // records the labels and constants an instruction defines and keeps it for the second pass
+    fn define(&mut self, parsed_instruction: Instruction) {
+        // reported once, at the first instruction or label that does not fit
+        if parsed_instruction.rom_address == ROM_SIZE && !self.rom_full {
+            self.rom_full = true;
+            self.errors.push(locate(
+                AssembleError::new(ErrorKind::ProgramTooLarge, &parsed_instruction.rep()),
+                &parsed_instruction,
+                &self.sources,
+            ));
+        }
+        let definition = match &parsed_instruction.variant {
+            // the symbol here is not a variable, it is a LABEL
+            // we match the LABEL to the memory address on the next unstruction
+            // past the end of ROM the address is wrong, but the file has already failed
+            InstructionVariant::Symbol {
+                symbol,
+                is_variable: false,
+            } => Some((symbol, self.symbol_table.add_symbol(symbol, parsed_instruction.rom_address as u16, false))),
+            // constants can only use symbols defined above them
+            InstructionVariant::Constant { name, value } => {
+                Some((name, define_constant(name, value, &mut self.symbol_table, self.parser.max_constant())))
+            }
+            _ => None,
+        };
+        if let Some((symbol, result)) = definition {
+            match result {
+                Ok(()) => {
+                    self.definition_sites.insert(Rc::clone(symbol), self.instructions.len());
+                }
+                Err(mut error) => {
+                    let duplicate = matches!(error.kind, ErrorKind::DuplicateLabel | ErrorKind::DuplicateSymbol);
+                    if let Some(&site) = self.definition_sites.get(symbol).filter(|_| duplicate) {
+                        let first = &self.instructions[site];
+                        error = error.with_related(Related {
+                            message: "first defined here".to_string(),
+                            file: first.file.to_string(),
+                            line: first.line_number,
+                            column: first.span.start,
+                            text: first.rep(),
+                        });
+                    }
+                    self.errors.push(locate(error, &parsed_instruction, &self.sources));
                 }
             }
         }
+        self.instructions.push(parsed_instruction);
     }
-    // remove the last trailing new line
-    if output_string.ends_with("\n"){
-        output_string.pop();
+
+    // reads the file `.include "path"` names in place of the directive
+    fn include(&mut self, path: &str, instruction: &Instruction) {
+        let located = |error| locate(error, instruction, &self.sources);
+        let candidates = include_candidates(path, &instruction.file, self.include_paths);
+        let Some(resolved) = candidates.iter().find(|candidate| candidate.is_file()) else {
+            let searched: Vec<String> = candidates.iter().map(|candidate| candidate.display().to_string()).collect();
+            let note = format!("searched {}", searched.join(", "));
+            let error = located(AssembleError::new(ErrorKind::IncludeNotFound, path).with_related(Related::note(&note)));
+            self.errors.push(error);
+            return;
+        };
+        let canonical_path = canonical(resolved);
+        if let Some(start) = self.include_stack.iter().position(|file| *file == canonical_path) {
+            // `a.asm -> b.asm -> a.asm`, from the first time the file was entered
+            let chain: Vec<String> = self.include_stack[start..]
+                .iter()
+                .chain([&canonical_path])
+                .map(|file| file.display().to_string())
+                .collect();
+            let note = format!("included by {}", chain.join(" -> "));
+            let error = located(AssembleError::new(ErrorKind::IncludeCycle, path).with_related(Related::note(&note)));
+            self.errors.push(error);
+            return;
+        }
+        let file: Rc<str> = Rc::from(resolved.to_string_lossy());
+        let site = IncludeSite {
+            file: instruction.file.to_string(),
+            line: instruction.line_number,
+            column: instruction.span.start,
+            macro_name: None,
+        };
+        let source: Rc<str> = match fs::read_to_string(resolved) {
+            Ok(source) => Rc::from(source),
+            Err(error) => {
+                let chain: Vec<IncludeSite> = [site].into_iter().chain(instruction.included_from.iter().cloned()).collect();
+                self.errors.push(AssembleError::io(&file, &error).included_from(&chain));
+                return;
+            }
+        };
+        self.sources.add(Rc::clone(&file), Rc::clone(&source));
+        self.include_stack.push(canonical_path);
+        let state = self.parser.enter_file(&file, 1, site);
+        self.read_file(&source);
+        self.parser.leave_file(state);
+        self.include_stack.pop();
     }
-    // I/O
-    // create directory if it doesnt exist
-    let dir = Path::new("output");
-    if !dir.exists(){
-        create_dir_all(dir)?;
+}
+
+// an error that gives up on an invocation and every expansion it is nested in, reported
+// with only the invocation that started it all, not every one of the nested expansions
+fn expansion_error(kind: ErrorKind, name: &str, instruction: &Instruction, sources: &Sources) -> AssembleError {
+    let outermost = instruction
+        .included_from
+        .iter()
+        .rposition(|site| site.macro_name.is_some())
+        .unwrap_or(0);
+    let error = AssembleError::new(kind, name).included_from(&instruction.included_from[outermost..]);
+    locate(error, instruction, sources)
+}
+
+// the macro invocation or pseudo-instruction an expansion is read for
+fn expansion_site(name: &str, instruction: &Instruction) -> IncludeSite {
+    IncludeSite {
+        file: instruction.file.to_string(),
+        line: instruction.line_number,
+        column: instruction.span.start,
+        macro_name: Some(name.to_string()),
     }
-    // create file_path
-    let file_path = dir.join(format!("{}.hack",extract_file_name(file_name_or_path)));
-    let mut file = File::create(file_path)?;
-    file.write_all(output_string.as_bytes())?;
+}
 
-    Ok(true)
+// where `.include "path"` may be, in the order they are tried: next to the including file,
+// then in each search directory
+fn include_candidates(path: &str, including_file: &str, include_paths: &[PathBuf]) -> Vec<PathBuf> {
+    let directory = Path::new(including_file).parent().unwrap_or(Path::new(""));
+    let mut candidates = vec![directory.join(path)];
+    if Path::new(path).is_relative() {
+        candidates.extend(include_paths.iter().map(|include_path| include_path.join(path)));
+    }
+    candidates
+}
+
+// one spelling for every path to a file, so `lib/../a.asm` and `a.asm` are the same file
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// evaluates the value of `.equ name value` and binds it in the symbol table
+fn define_constant(
+    name: &Name,
+    value: &Expression,
+    symbol_table: &mut SymbolTable,
+    max_constant: u32,
+) -> Result<(), AssembleError> {
+    let evaluated = value
+        .evaluate(&mut |symbol| symbol_table.get_memory_address(symbol))
+        .map_err(|error| match error.kind {
+            ErrorKind::UnknownSymbol => error.with_related(Related::note("constants can only use symbols defined above them")),
+            _ => error,
+        })?;
+    match u32::try_from(evaluated) {
+        // a constant is only ever loaded by an A-instruction, it has to fit in one
+        Ok(evaluated) if evaluated <= max_constant => symbol_table.add_constant(name, evaluated as u16),
+        _ => Err(AssembleError::new(ErrorKind::ConstantOutOfRange, &value.to_string())),
+    }
+}
+
+// `@LOOOP` followed by a jump would silently allocate a variable and jump to its RAM address,
+// that is always a misspelled or missing label
+// `jumps` is whether the next instruction that takes a word jumps
+fn undefined_jump_target(
+    instruction: &Instruction,
+    jumps: bool,
+    symbol_table: &SymbolTable,
+) -> Option<AssembleError> {
+    let InstructionVariant::Symbol {
+        symbol,
+        is_variable: true,
+    } = &instruction.variant
+    else {
+        return None;
+    };
+    if !jumps || symbol_table.contains_symbol(symbol) {
+        return None;
+    }
+    Some(AssembleError::new(ErrorKind::UndefinedLabel, symbol).with_suggestion(symbol_table.closest_label(symbol)))
+}
+
+// points an error raised while handling an instruction at its source line
+fn locate(error: AssembleError, instruction: &Instruction, sources: &Sources) -> AssembleError {
+    let line = sources.line(&instruction.file, instruction.line_number);
+    // only search the instruction itself, not any comment that follows it
+    let source: String = line
+        .chars()
+        .skip(instruction.span.start - 1)
+        .take(instruction.span.end - instruction.span.start)
+        .collect();
+    error
+        .locate(&instruction.file, instruction.line_number, &source, instruction.span.start)
+        .included_from(&instruction.included_from)
+}
+
+// returns the machine code word for a single instruction, labels produce no word
+fn translate(
+    parsed_instruction: &Instruction,
+    symbol_table: &mut SymbolTable,
+    max_constant: u32,
+) -> Result<Option<u16>, Vec<AssembleError>> {
+    match &parsed_instruction.variant {
+        InstructionVariant::Symbol {
+            symbol,
+            is_variable,
+        } => {
+            if !is_variable {
+                return Ok(None);
+            }
+            // the symbol here is a variable
+            // check if the variable is in memory
+            symbol_table
+                .add_symbol(symbol, symbol_table.get_next_free_address(), true)
+                .map_err(|error| vec![error])?;
+            let memory_address = symbol_table
+                .get_memory_address(symbol)
+                .map_err(|error| vec![error])?;
+            Ok(Some(memory_address))
+        }
+        InstructionVariant::Constant { .. }
+        | InstructionVariant::Include { .. }
+        | InstructionVariant::Invocation { .. }
+        | InstructionVariant::Pseudo { .. }
+        | InstructionVariant::If(_) => Ok(None),
+        // the word of an A instruction is its value, the parser keeps it below 32768 so the top bit
+        // stays 0 unless raw words were asked for
+        InstructionVariant::A(constant) => Ok(Some(*constant)),
+        InstructionVariant::Expression(expression) => {
+            // every label is known by now, variables only once they have been allocated
+            let value = expression
+                .evaluate(&mut |symbol| symbol_table.get_memory_address(symbol))
+                .map_err(|error| vec![error])?;
+            match u32::try_from(value) {
+                Ok(value) if value <= max_constant => Ok(Some(value as u16)),
+                _ => Err(vec![AssembleError::new(ErrorKind::ConstantOutOfRange, &expression.to_string())]),
+            }
+        }
+        InstructionVariant::C { comp, dest, jmp, .. } => Ok(Some(translate_c_instruction(*dest, *comp, *jmp))),
+    }
 }
 
 #[cfg(test)]
@@ -123,9 +744,659 @@ mod tests {
 
     #[test]
     fn parser_test() {
-        let assembler_1 = assemble("./input/Add.asm");
-        let assembler_2 = assemble("./input/Max.asm");
-        let assembler_3 = assemble("./input/Rect.asm");
+        assert!(assemble("./input/Add.asm").is_ok());
+        assert!(assemble("./input/Max.asm").is_ok());
+        assert!(assemble("./input/Rect.asm").is_ok());
+    }
+
+    #[test]
+    fn test_assemble_str() {
+        let program = assemble_str("(LOOP)\n@i\nM=M+1\n@LOOP\n0;JMP\n(UNUSED)\n").unwrap();
+        assert_eq!(program.machine_code, vec![16, 0b1111110111001000, 0, 0b1110101010000111]);
+        assert_eq!(program.labels, BTreeMap::from([("LOOP".to_string(), 0), ("UNUSED".to_string(), 4)]));
+        assert_eq!(program.variables, BTreeMap::from([("i".to_string(), 16)]));
+        assert_eq!(program.warnings.len(), 2);
+        assert_eq!(program.hack_text().lines().count(), 4);
+        let errors = assemble_str("@1\nD=X\n").unwrap_err();
+        assert_eq!((errors[0].file.as_str(), errors[0].line), (INPUT_NAME, 2));
+    }
+
+    #[test]
+    fn test_a_instruction_words() {
+        // the word is the value itself, whether written as a number, a symbol or an expression
+        let program = assemble_str("@0\n@14\n@1\n@16384\n@SCREEN+2\n@x\n").unwrap();
+        assert_eq!(program.machine_code, vec![0, 0b1110, 1, 0b0100000000000000, 16386, 16]);
+    }
+
+    #[test]
+    fn test_assemble_reader() {
+        let source = "@2\nD=A\n@3\nD=D+A\n@0\nM=D\n";
+        let program = assemble_reader(source.as_bytes()).unwrap();
+        assert_eq!(program, assemble_str(source).unwrap());
+        let options = AssembleOptions::default();
+        let errors = assemble_reader_with_options("grader.asm", io::Cursor::new("D=X"), &options).unwrap_err();
+        assert_eq!(errors[0].file, "grader.asm");
+        // invalid UTF-8 cannot be Hack assembly
+        let errors = assemble_reader(&[0xff, 0xfe][..]).unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::Io(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let errors = assemble("./input/Missing.asm").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::Io(io::ErrorKind::NotFound));
+    }
+
+    fn write_input(name: &str, source: &str) -> String {
+        let dir = std::env::temp_dir().join("hack_assembler_tests");
+        create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, source).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_reports_every_error() {
+        let path = write_input(
+            "Errors.asm",
+            "@70000\nD=D+X\nQ=M;JMPP\n(\n@5\nD=M=A\n",
+        );
+        let errors = assemble(&path).unwrap_err();
+        let found: Vec<(ErrorKind, usize, usize)> = errors
+            .iter()
+            .map(|error| (error.kind.clone(), error.line, error.column))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (ErrorKind::ConstantOutOfRange, 1, 2),
+                (ErrorKind::InvalidComp, 2, 3),
+                (ErrorKind::InvalidDest, 3, 1),
+                (ErrorKind::InvalidJump, 3, 5),
+                (ErrorKind::InvalidLabel, 4, 1),
+                (ErrorKind::InvalidInstruction, 6, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors_point_at_source_lines() {
+        // blank lines, comments and labels must not shift the reported position
+        let path = write_input(
+            "Located.asm",
+            "// header\n\n(LOOP)\n  @LOOP\n\n    D = M ; JMPP // typo\n",
+        );
+        let errors = assemble(&path).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].file, path);
+        assert_eq!((errors[0].line, errors[0].column), (6, 13));
+    }
+
+    #[test]
+    fn test_jump_to_misspelled_label() {
+        let path = write_input(
+            "Misspelled.asm",
+            "(LOOP)\n@i\nM=M+1\n@LOOOP\n0;JMP\n@i\nD=M;JMPP\n",
+        );
+        let errors = assemble(&path).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind, ErrorKind::UndefinedLabel);
+        assert_eq!(errors[0].line, 4);
+        assert_eq!(errors[0].column, 2);
+        assert_eq!(errors[0].suggestion.as_deref(), Some("LOOP"));
+        assert_eq!(errors[1].kind, ErrorKind::InvalidJump);
+        assert_eq!(errors[1].suggestion.as_deref(), Some("JMP"));
+    }
+
+    #[test]
+    fn test_warnings_keep_output() {
+        let path = write_input("Warned.asm", "(UNUSED)\n@2\nD=A\n");
+        let warnings = assemble(&path).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].lint, lint::Lint::UnusedLabel);
+        let output = std::fs::read_to_string("output/Warned.hack").unwrap();
+        assert_eq!(output, "0000000000000010\n1110110000010000");
+    }
+
+    #[test]
+    fn test_denied_lint_is_an_error() {
+        let path = write_input("Denied.asm", "(UNUSED)\n@2\nD=A\n");
+        let mut options = AssembleOptions::default();
+        options.lints.set(lint::Lint::UnusedLabel, LintLevel::Deny);
+        let errors = assemble_with_options(&path, &options).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::Lint(lint::Lint::UnusedLabel));
+        assert_eq!((errors[0].line, errors[0].column), (1, 1));
+    }
+
+    #[test]
+    fn test_duplicate_labels() {
+        let path = write_input(
+            "Duplicate.asm",
+            "(LOOP)\n@LOOP\n0;JMP\n(LOOP)\n@LOOP\nD;JGT\n(SCREEN)\n",
+        );
+        let errors = assemble(&path).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind, ErrorKind::DuplicateLabel);
+        assert_eq!((errors[0].line, errors[0].column), (4, 2));
+        let first = errors[0].related.as_ref().unwrap();
+        assert_eq!((first.line, first.column, first.text.as_str()), (1, 1, "(LOOP)"));
+        assert_eq!(errors[1].kind, ErrorKind::PredefinedLabel);
+        assert_eq!(errors[1].line, 7);
+    }
+
+    #[test]
+    fn test_raw_words() {
+        let path = write_input("RawWords.asm", "@40000\nD=A\n");
+        let errors = assemble(&path).unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::ConstantOutOfRange);
+        let options = AssembleOptions {
+            allow_raw_words: true,
+            ..AssembleOptions::default()
+        };
+        assert!(assemble_with_options(&path, &options).is_ok());
+        let output = std::fs::read_to_string("output/RawWords.hack").unwrap();
+        assert_eq!(output, "1001110001000000\n1110110000010000");
+    }
+
+    #[test]
+    fn test_commuted_comps_assemble_like_canonical_ones() {
+        let path = write_input("Commuted.asm", "D=A+D\nM=M|D;JNE\nAD=1+M\n");
+        assert!(assemble(&path).is_ok());
+        let commuted = std::fs::read_to_string("output/Commuted.hack").unwrap();
+        let path = write_input("Canonical.asm", "D=D+A\nM=D|M;JNE\nAD=M+1\n");
+        assert!(assemble(&path).is_ok());
+        assert_eq!(commuted, std::fs::read_to_string("output/Canonical.hack").unwrap());
+    }
+
+    #[test]
+    fn test_expressions() {
+        let path = write_input(
+            "Expressions.asm",
+            "@SCREEN+32*2
+D=A
+@(KBD-SCREEN)/2
+D=A
+@END-1
+0;JMP
+(END)
+@END
+0;JMP
+",
+        );
+        assert!(assemble(&path).is_ok());
+        let output = std::fs::read_to_string("output/Expressions.hack").unwrap();
+        let words: Vec<&str> = output.lines().collect();
+        assert_eq!(words[0], format!("{:016b}", 16384 + 64));
+        assert_eq!(words[2], format!("{:016b}", 4096));
+        assert_eq!(words[4], format!("{:016b}", 5));
+    }
+
+    #[test]
+    fn test_expression_errors() {
+        let path = write_input(
+            "ExpressionErrors.asm",
+            "@SCREEN/(KBD-KBD)
+D=A
+@SCREEN*2
+D=A
+@ENDD+1
+D=A
+(END)
+",
+        );
+        let errors = assemble(&path).unwrap_err();
+        let found: Vec<(ErrorKind, usize, usize)> = errors
+            .iter()
+            .map(|error| (error.kind.clone(), error.line, error.column))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (ErrorKind::DivisionByZero, 1, 2),
+                (ErrorKind::ConstantOutOfRange, 3, 2),
+                (ErrorKind::UnknownSymbol, 5, 2),
+            ]
+        );
+        assert_eq!(errors[2].suggestion.as_deref(), Some("END"));
+    }
+
+    #[test]
+    fn test_block_comments() {
+        let path = write_input(
+            "BlockComments.asm",
+            "/* adds\n   2 and 3 */\n@2\nD=A /* two */\n/*\n*/ @3\nD=D+A\n/* typo\n*/ D=X\n",
+        );
+        let errors = assemble(&path).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].kind.clone(), errors[0].line, errors[0].column), (ErrorKind::InvalidComp, 9, 6));
+        let path = write_input("OpenComment.asm", "@2\nD=A /* never\nclosed\n");
+        let errors = assemble(&path).unwrap_err();
+        assert_eq!((errors[0].kind.clone(), errors[0].line, errors[0].column), (ErrorKind::UnterminatedComment, 2, 5));
+    }
+
+    #[test]
+    fn test_comment_style() {
+        let source = "@2 // two\nD=A /* two */\n";
+        assert!(assemble_str(source).is_ok());
+        let options = AssembleOptions {
+            comment_style: CommentStyle::Line,
+            ..AssembleOptions::default()
+        };
+        let errors = assemble_str_with_options("Main.asm", source, &options).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].kind.clone(), errors[0].line, errors[0].column), (ErrorKind::InvalidComp, 2, 3));
+    }
+
+    #[test]
+    fn test_program_too_large() {
+        let path = write_input("Full.asm", &"D=D+1\n".repeat(ROM_SIZE));
+        assert!(assemble(&path).is_ok());
+        let path = write_input("TooLarge.asm", &format!("{}(END)\n@END\n0;JMP\n", "D=D+1\n".repeat(ROM_SIZE)));
+        let errors = assemble(&path).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].kind.clone(), errors[0].line), (ErrorKind::ProgramTooLarge, ROM_SIZE + 1));
+    }
+
+    #[test]
+    fn test_constants() {
+        let program = assemble_str(
+            "@ROWS\nD=A\n.equ ROWS 256\n#define WIDTH 32 // words\n.equ SIZE ROWS*WIDTH\n@SCREEN+SIZE-1\nD=A\n@i\nM=D\n",
+        )
+        .unwrap();
+        assert_eq!(program.machine_code[0], 256);
+        assert_eq!(program.machine_code[2], 16384 + 8192 - 1);
+        // constants take no RAM, the first variable still gets address 16
+        assert_eq!(program.machine_code[4], 16);
+        assert_eq!(program.constants["SIZE"], 8192);
+        assert_eq!(program.variables.len(), 1);
+        // only `@i` is a variable used once, constants used once are fine
+        assert_eq!(program.warnings.len(), 1);
+        assert_eq!(program.warnings[0].line, 8);
+    }
+
+    #[test]
+    fn test_constant_errors() {
+        let errors = assemble_str(
+            ".equ N 1\n.equ N 2\n(N)\n.equ SCREEN 0\n.equ BIG 0x7fff+1\n.equ LATE END\n(END)\n",
+        )
+        .unwrap_err();
+        let found: Vec<(ErrorKind, usize)> = errors.iter().map(|error| (error.kind.clone(), error.line)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (ErrorKind::DuplicateSymbol, 2),
+                (ErrorKind::DuplicateSymbol, 3),
+                (ErrorKind::DuplicateSymbol, 4),
+                (ErrorKind::ConstantOutOfRange, 5),
+                (ErrorKind::UnknownSymbol, 6),
+            ]
+        );
+        assert_eq!(errors[1].related.as_ref().unwrap().line, 1);
+        assert_eq!(errors[4].column, 11);
+        assert_eq!(errors[4].related.as_ref().unwrap().message, "constants can only use symbols defined above them");
+    }
+
+    // assembles a file written with write_input, giving back the whole program
+    fn assemble_path(path: &str, options: &AssembleOptions) -> Result<Program, Vec<AssembleError>> {
+        assemble_str_with_options(path, &std::fs::read_to_string(path).unwrap(), options)
+    }
+
+    #[test]
+    fn test_includes() {
+        write_input("include/lib/math.asm", "(DOUBLE)\nD=D+A\n.include \"consts.asm\"\n");
+        write_input("include/lib/consts.asm", ".equ TWO 2\n");
+        write_input("include/shared/screen.asm", "@SCREEN+TWO\n");
+        let main = write_input(
+            "include/Main.asm",
+            "@TWO\n.include \"lib/math.asm\"\n.include \"screen.asm\"\n@DOUBLE\n0;JMP\n",
+        );
+        let shared = std::path::Path::new(&main).with_file_name("shared");
+        let options = AssembleOptions {
+            include_paths: vec![shared.clone()],
+            ..AssembleOptions::default()
+        };
+        let program = assemble_path(&main, &options).unwrap();
+        assert_eq!(program.machine_code, vec![2, 0b1110000010010000, 16386, 1, 0b1110101010000111]);
+        assert_eq!(program.labels["DOUBLE"], 1);
+        assert_eq!(program.constants["TWO"], 2);
+        // without the search path screen.asm is only looked for next to Main.asm
+        let errors = assemble_path(&main, &AssembleOptions::default()).unwrap_err();
+        assert_eq!((errors[0].kind.clone(), errors[0].line, errors[0].column), (ErrorKind::IncludeNotFound, 3, 11));
+        assert_eq!(errors[0].text, "screen.asm");
+        let searched = errors[0].related.as_ref().unwrap().message.clone();
+        assert!(searched.starts_with("searched ") && searched.ends_with("include/screen.asm"));
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let math = write_input("include_errors/math.asm", "@2\nD=X\n");
+        let main = write_input("include_errors/Main.asm", "D=A\n  .include \"math.asm\"\n");
+        let errors = assemble_path(&main, &AssembleOptions::default()).unwrap_err();
+        assert_eq!((errors[0].file.as_str(), errors[0].line), (math.as_str(), 2));
+        let site = IncludeSite { file: main.clone(), line: 2, column: 3, macro_name: None };
+        assert_eq!(*errors[0].included_from, [site]);
+        // a.asm -> b.asm -> a.asm
+        write_input("include_cycle/b.asm", "@1\n.include \"a.asm\"\n");
+        let a = write_input("include_cycle/a.asm", ".include \"b.asm\"\n");
+        let errors = assemble_path(&a, &AssembleOptions::default()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].kind.clone(), errors[0].line), (ErrorKind::IncludeCycle, 2));
+        assert!(errors[0].file.ends_with("b.asm"));
+        assert_eq!(errors[0].included_from[0].file, a);
+        let chain = errors[0].related.as_ref().unwrap().message.clone();
+        assert!(chain.starts_with("included by ") && chain.contains("a.asm -> ") && chain.ends_with("a.asm"));
+        // a file included from two places is read twice
+        write_input("include_twice/empty.asm", "// nothing\n");
+        let twice = write_input("include_twice/Main.asm", ".include \"empty.asm\"\n.include \"empty.asm\"\n@0\n");
+        assert!(assemble_path(&twice, &AssembleOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_errors_in_program_order() {
+        // an undefined jump target is found in the second pass, the invalid comps in the first
+        let lib = write_input("include_order/lib.asm", "@1\n@LOOOP\n0;JMP\nD=Y\n");
+        let main = write_input("include_order/Main.asm", ".include \"lib.asm\"\nD=X\n(LOOP)\n@LOOP\n0;JMP\n");
+        let errors = assemble_path(&main, &AssembleOptions::default()).unwrap_err();
+        let locations: Vec<(&str, usize)> = errors.iter().map(|error| (error.file.as_str(), error.line)).collect();
+        assert_eq!(locations, vec![(lib.as_str(), 2), (lib.as_str(), 4), (main.as_str(), 2)]);
+        // denied lints too
+        write_input("include_order/unused.asm", "@1\n@2\n(UNUSED)\n@0\n");
+        let main = write_input("include_order/Lints.asm", ".include \"unused.asm\"\n(ALSO_UNUSED)\n@0\n");
+        let mut options = AssembleOptions::default();
+        options.lints.set(lint::Lint::UnusedLabel, LintLevel::Deny);
+        let errors = assemble_path(&main, &options).unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|error| error.line).collect();
+        assert_eq!(lines, vec![3, 2]);
+        assert!(errors[0].file.ends_with("unused.asm"));
+    }
+
+    #[test]
+    fn test_macros() {
+        let source = "\
+.macro PUSH_D
+    @SP
+    AM=M+1
+    A=A-1
+    M=D
+.endm
+.macro COPY src, dst
+(WAIT)
+    @src
+    D=M;JEQ
+    @WAIT
+    @dst+1
+    M=D
+    PUSH_D
+.endm
+    COPY R1, R2
+    COPY KBD, SCREEN
+";
+        let program = assemble_str(source).unwrap();
+        assert_eq!(program.machine_code.len(), 18);
+        assert_eq!(&program.machine_code[..5], &[1, 0b1111110000010000 | 0b010, 0, 3, 0b1110001100001000]);
+        assert_eq!(program.machine_code[12], 16385);
+        // every expansion gets its own copy of the labels in the body
+        assert_eq!(program.labels["WAIT$COPY.1"], 0);
+        assert_eq!(program.labels["WAIT$COPY.3"], 9);
+        assert_eq!(program.machine_code[11], 9);
+    }
+
+    #[test]
+    fn test_macro_argument_precedence() {
+        let source = ".macro DBL x
+@x*2
+.endm
+DBL 1+2
+DBL SCREEN-1
+";
+        let program = assemble_str(source).unwrap();
+        // `(1+2)*2`, not `1+2*2`
+        assert_eq!(program.machine_code, vec![6, 32766]);
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        let source = ".macro SET dst, value\n  @value\n  D=A\n  @dst\n  M=X\n.endm\nSET R0, 5\n";
+        let errors = assemble_str(source).unwrap_err();
+        // reported where the body was written, with the invocation in a note
+        assert_eq!((errors[0].kind.clone(), errors[0].line, errors[0].column), (ErrorKind::InvalidComp, 5, 5));
+        let site = IncludeSite { file: INPUT_NAME.to_string(), line: 7, column: 1, macro_name: Some("SET".to_string()) };
+        assert_eq!(*errors[0].included_from, [site]);
+        // columns after a substituted argument point back into the body
+        let errors = assemble_str(".macro AT addr\n  @addr+1+\n.endm\nAT SCREEN\n").unwrap_err();
+        assert_eq!((errors[0].line, errors[0].column), (2, 10));
+        // endless recursion stops at the limit with a single error
+        let errors = assemble_str(".macro A\n@1\nB\n.endm\n.macro B\nA\n.endm\nA\nA\n").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].kind.clone(), errors[0].line), (ErrorKind::MacroRecursion, 6));
+        assert_eq!((errors[0].included_from[0].line, errors[0].included_from.len()), (8, 1));
+        assert_eq!(errors[1].included_from[0].line, 9);
+        let errors = assemble_str("@1\n.macro OPEN\n@2\n").unwrap_err();
+        assert_eq!((errors[0].kind.clone(), errors[0].line), (ErrorKind::UnterminatedMacro, 2));
+    }
+
+    // macros M1..M30 where each one invokes the one before it twice, 2^30 copies of the body of M0
+    fn doubling_macros(body: &str) -> String {
+        let mut source = format!(".macro M0\n{}\n.endm\n", body);
+        for level in 1..=30 {
+            source.push_str(&format!(".macro M{}\nM{}\nM{}\n.endm\n", level, level - 1, level - 1));
+        }
+        source.push_str("M30\nM30\n");
+        source
+    }
+
+    #[test]
+    fn test_expansion_limits() {
+        // nothing but comments, the expansions are given up on once they are too large
+        let errors = assemble_str(&doubling_macros("// nothing")).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::ExpansionTooLarge);
+        // reported at the invocation that went over, with the one that started it all in a note
+        assert_eq!((errors[0].included_from.len(), errors[0].included_from[0].line), (1, 124));
+        // instructions fill the ROM first, nothing is expanded after that
+        let errors = assemble_str(&doubling_macros("D=D+1")).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::ProgramTooLarge);
+    }
+
+    #[test]
+    fn test_pseudo_instructions() {
+        let source = "\
+    ld D, 3
+(LOOP)
+    if D<=0 goto END     // done
+    dec D
+    mov RAM[sum], D
+    goto LOOP
+(END)
+    goto END
+";
+        let options = AssembleOptions {
+            pseudo_instructions: true,
+            listing: true,
+            ..AssembleOptions::default()
+        };
+        let program = assemble_str_with_options("Count.asm", source, &options).unwrap();
+        // labels after a pseudo-instruction count every word of its expansion
+        assert_eq!(program.labels["LOOP"], 2);
+        assert_eq!(program.labels["END"], 9);
+        assert_eq!(program.machine_code.len(), 11);
+        assert_eq!(&program.machine_code[2..4], &[9, 0b1110001100000110]);
+        let listing = program.listing_text();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "00000  0000000000000011  @3                        Count.asm:1  from `ld D, 3`");
+        assert!(lines[2].starts_with("00002                    (LOOP)  "));
+        assert!(lines[4].ends_with("Count.asm:3  from `if D<=0 goto END`"));
+        assert_eq!(program.listing.len(), 13);
+        let options = AssembleOptions { listing: false, ..options };
+        assert!(assemble_str_with_options("Count.asm", source, &options).unwrap().listing.is_empty());
+        // errors in an operand point at it
+        let errors = assemble_str_with_options("Count.asm", "inc 1x\n", &options).unwrap_err();
+        assert_eq!((errors[0].kind.clone(), errors[0].column), (ErrorKind::InvalidSymbol, 5));
+        assert_eq!(errors[0].included_from[0].macro_name.as_deref(), Some("inc"));
+        assert!(assemble_str(source).is_err());
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let source = "\
+.equ LEVEL 2
+    @R0
+.ifdef DEBUG
+    D=M
+    @TRACE          // debug builds record every value
+    M=D
+.endif
+.if LEVEL - 1
+  .ifndef DEBUG
+    @1
+  .else
+    @2
+  .endif
+.else
+    @3
+.endif
+(END)
+";
+        let release = assemble_str(source).unwrap();
+        assert_eq!(release.machine_code, vec![0, 1]);
+        assert_eq!(release.labels["END"], 2);
+        let options = AssembleOptions {
+            defines: vec![("DEBUG".to_string(), 1)],
+            ..AssembleOptions::default()
+        };
+        let debug = assemble_str_with_options(INPUT_NAME, source, &options).unwrap();
+        assert_eq!(debug.machine_code.len(), 5);
+        assert_eq!(debug.labels["END"], 5);
+        assert_eq!(debug.constants["DEBUG"], 1);
+        // a name defined on the command line cannot be defined again with `.equ`
+        let options = AssembleOptions {
+            defines: vec![("LEVEL".to_string(), 2)],
+            ..AssembleOptions::default()
+        };
+        let errors = assemble_str_with_options(INPUT_NAME, source, &options).unwrap_err();
+        assert_eq!((errors[0].kind.clone(), errors[0].line), (ErrorKind::DuplicateSymbol, 1));
+        let options = AssembleOptions {
+            defines: vec![("SP".to_string(), 2)],
+            ..AssembleOptions::default()
+        };
+        let errors = assemble_str_with_options(INPUT_NAME, "@1\n", &options).unwrap_err();
+        assert_eq!((errors[0].kind.clone(), errors[0].line), (ErrorKind::DuplicateSymbol, 0));
+        assert!(assemble_str(".if 0\n@1\n.endif\n").unwrap().machine_code.is_empty());
+    }
+
+    #[test]
+    fn test_conditional_errors() {
+        // labels below the condition have no address yet
+        let errors = assemble_str(".if END
+@1
+.else
+@2
+.endif
+(END)
+").unwrap_err();
+        assert_eq!((errors[0].kind.clone(), errors[0].column), (ErrorKind::UnknownSymbol, 5));
+        assert_eq!(errors.len(), 1);
+        let errors = assemble_str("@1
+.ifdef DEBUG
+.if 1
+.endif
+").unwrap_err();
+        assert_eq!((errors[0].kind.clone(), errors[0].line), (ErrorKind::UnterminatedConditional, 2));
+        assert_eq!(errors[0].text, ".ifdef");
+        let errors = assemble_str("@1
+.else
+").unwrap_err();
+        assert_eq!((errors[0].kind.clone(), errors[0].line), (ErrorKind::UnexpectedToken, 2));
+        // a block opened in a macro body has to be closed there
+        let errors = assemble_str(".macro M
+.if 1
+@1
+.endm
+M
+.endif
+").unwrap_err();
+        assert_eq!((errors[0].kind.clone(), errors[0].line), (ErrorKind::UnterminatedConditional, 2));
+        assert_eq!(errors[1].line, 6);
+    }
+
+    #[test]
+    fn test_parse_define() {
+        assert_eq!(parse_define("DEBUG"), Ok(("DEBUG".to_string(), 1)));
+        assert_eq!(parse_define("LEVEL=0x10"), Ok(("LEVEL".to_string(), 16)));
+        assert!(parse_define("9x=1").is_err());
+        assert!(parse_define("A B=1").is_err());
+        assert!(parse_define("LEVEL=").is_err());
+        assert!(parse_define("LEVEL=32768").is_err());
+    }
+
+    // cargo test --release assembler_bench_throughput -- --ignored --nocapture
+    #[test]
+    #[ignore = "benchmark"]
+    fn assembler_bench_throughput() {
+        // ROM holds 32768 words, so a valid 1M-line file is mostly what takes no ROM:
+        // labels, constants and comments, around one A- or C-instruction every few blocks
+        let mut source = String::new();
+        for block in 0..25_000 {
+            source.push_str(&format!("// function f{}\n/* generated */\n(f{})\n", block, block));
+            for label in 0..12 {
+                source.push_str(&format!("(f{}$l{})  // local label\n", block, label));
+            }
+            for constant in 0..12 {
+                source.push_str(&format!(".equ K{}.{} {}\n", block, constant, constant));
+            }
+            for _ in 0..12 {
+                source.push_str("    // comment\n");
+            }
+            let instruction = match block % 4 {
+                0 => format!("@v{}", block % 1000),
+                1 => format!("@f{}$l3+K{}.2", block, block),
+                2 => "D=D+M".to_string(),
+                _ => format!("@f{}", block - 3),
+            };
+            source.push_str(&format!("    {}\n", instruction));
+        }
+        let lines = source.lines().count();
+        assert_eq!(lines, 1_000_000);
+        let start = std::time::Instant::now();
+        let program = assemble_str(&source).unwrap();
+        let elapsed = start.elapsed().as_secs_f64();
+        assert_eq!(program.machine_code.len(), 25_000);
+        println!(
+            "{} lines, {:.1} MB in {:.3}s: {:.0} lines/s, {:.1} MB/s",
+            lines,
+            source.len() as f64 / 1e6,
+            elapsed,
+            lines as f64 / elapsed,
+            source.len() as f64 / 1e6 / elapsed,
+        );
+    }
+
+    #[test]
+    fn test_max_errors() {
+        let path = write_input("MaxErrors.asm", "D=X\nD=Y\nD=Z\n");
+        let options = AssembleOptions {
+            max_errors: Some(2),
+            ..AssembleOptions::default()
+        };
+        let errors = assemble_with_options(&path, &options).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[1].line, 2);
+    }
+
+    #[test]
+    fn test_max_errors_across_passes() {
+        // the undefined label is found in the second pass, after the invalid comp below it
+        let options = AssembleOptions {
+            max_errors: Some(1),
+            ..AssembleOptions::default()
+        };
+        let errors = assemble_str_with_options("Main.asm", "(LOOP)\n@LOOOP\n0;JMP\nD=X\n", &options).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].kind.clone(), errors[0].line), (ErrorKind::UndefinedLabel, 2));
     }
 
     #[test]
@@ -0,0 +1,607 @@
+// renders assembler errors the way rustc does:
+//
+// error[H0004]: invalid jump mnemonic `JMPP`
+//  --> input/Max.asm:3:5
+//   |
+// 3 |   0;JMPP
+//   |     ^^^^
+//   |
+//   = help: valid jumps are JGT, JEQ, JGE, JLT, JNE, JLE and JMP
+//
+// the source line is looked up from the file named in the error, or from text registered up front
+
+use std::{collections::HashMap, fmt::Write, fs};
+
+use crate::{
+    error::{AssembleError, ErrorKind, IncludeSite},
+    lint::Warning,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        }
+    }
+}
+
+// everything the renderer needs to print one message, a line of 0 means the whole file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // identifies the kind of message independently of its wording
+    pub code: String,
+    pub message: String,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    // number of characters to underline, starting at column
+    pub width: usize,
+    pub help: Option<String>,
+    pub note: Option<String>,
+    pub secondary: Option<Secondary>,
+    // text that can replace the underlined columns to fix the problem
+    pub suggestion: Option<String>,
+    // printed as `included from` notes, and listed in JSON
+    pub included_from: Vec<IncludeSite>,
+}
+
+// another location shown below the main one, underlined with `-` and labelled
+#[derive(Debug, Clone, PartialEq)]
+pub struct Secondary {
+    pub label: String,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub width: usize,
+}
+
+impl From<&AssembleError> for Diagnostic {
+    fn from(error: &AssembleError) -> Self {
+        Self {
+            severity: Severity::Error,
+            code: error.kind.code().to_string(),
+            message: match error.kind {
+                ErrorKind::Io(_) => format!("{}: {}", error.kind.message(), error.text),
+                _ => format!("{} `{}`", error.kind.message(), error.text),
+            },
+            file: error.file.clone(),
+            line: error.line,
+            column: error.column,
+            width: error.text.chars().count().max(1),
+            help: match &error.suggestion {
+                Some(suggestion) => Some(format!("did you mean `{}`?", suggestion)),
+                None => error.kind.help().map(str::to_string),
+            },
+            note: match (&error.kind, &error.related) {
+                (_, Some(related)) if related.line == 0 => Some(related.message.clone()),
+                (ErrorKind::Lint(lint), _) => Some(format!("`-D {}` turns this warning into an error", lint.name())),
+                _ => None,
+            },
+            secondary: error
+                .related
+                .as_ref()
+                .filter(|related| related.line > 0)
+                .map(|related| Secondary {
+                    label: related.message.clone(),
+                    file: related.file.clone(),
+                    line: related.line,
+                    column: related.column,
+                    width: related.text.chars().count().max(1),
+                }),
+            suggestion: error.suggestion.clone(),
+            included_from: error.included_from.to_vec(),
+        }
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        Self {
+            severity: Severity::Warning,
+            code: warning.lint.code().to_string(),
+            message: format!("{} `{}`", warning.lint.message(), warning.text),
+            file: warning.file.clone(),
+            line: warning.line,
+            column: warning.column,
+            width: warning.text.chars().count().max(1),
+            help: match &warning.suggestion {
+                Some(suggestion) => Some(format!("write it as `{}`", suggestion)),
+                None => Some(warning.lint.help().to_string()),
+            },
+            note: Some(format!("pass `-A {}` to silence this warning", warning.lint.name())),
+            secondary: None,
+            suggestion: warning.suggestion.clone(),
+            included_from: warning.included_from.to_vec(),
+        }
+    }
+}
+
+impl Diagnostic {
+    // one line of JSON for editors and CI, columns are 1-based and column_end is exclusive
+    // line and columns are null when the message is about the whole file
+    pub fn to_json(&self) -> String {
+        let location = |line: usize, column: usize, width: usize| {
+            if line == 0 {
+                "\"line\":null,\"column_start\":null,\"column_end\":null".to_string()
+            } else {
+                format!(
+                    "\"line\":{},\"column_start\":{},\"column_end\":{}",
+                    line,
+                    column,
+                    column + width
+                )
+            }
+        };
+        let related = match &self.secondary {
+            Some(secondary) => format!(
+                "{{\"message\":{},\"file\":{},{}}}",
+                json_string(&secondary.label),
+                json_string(&secondary.file),
+                location(secondary.line, secondary.column, secondary.width)
+            ),
+            None => "null".to_string(),
+        };
+        let included_from: Vec<String> = self
+            .included_from
+            .iter()
+            .map(|site| {
+                format!(
+                    "{{\"file\":{},\"line\":{},\"column\":{},\"macro\":{}}}",
+                    json_string(&site.file),
+                    site.line,
+                    site.column,
+                    json_optional(&site.macro_name)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"type\":\"diagnostic\",\"severity\":{},\"code\":{},\"message\":{},\"file\":{},{},\"suggestion\":{},\"help\":{},\"note\":{},\"related\":{},\"included_from\":[{}]}}",
+            json_string(self.severity.name()),
+            json_string(&self.code),
+            json_string(&self.message),
+            json_string(&self.file),
+            location(self.line, self.column, self.width),
+            json_optional(&self.suggestion),
+            json_optional(&self.help),
+            json_optional(&self.note),
+            related,
+            included_from.join(",")
+        )
+    }
+}
+
+// the last line of JSON output, totals for the whole run
+pub fn json_summary(files: usize, failed: usize, errors: usize, warnings: usize) -> String {
+    format!(
+        "{{\"type\":\"summary\",\"files\":{},\"assembled\":{},\"failed\":{},\"errors\":{},\"warnings\":{}}}",
+        files,
+        files - failed,
+        failed,
+        errors,
+        warnings
+    )
+}
+
+fn json_optional(text: &Option<String>) -> String {
+    text.as_deref().map_or("null".to_string(), json_string)
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for char in text.chars() {
+        match char {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            char if (char as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", char as u32);
+            }
+            char => escaped.push(char),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+const TAB_WIDTH: usize = 4;
+
+pub struct Renderer {
+    color: bool,
+    // source lines by file name, None when the file could not be read
+    sources: HashMap<String, Option<Vec<String>>>,
+}
+
+impl Renderer {
+    pub fn new(color: bool) -> Self {
+        Self {
+            color,
+            sources: HashMap::new(),
+        }
+    }
+
+    // use `source` for `file` instead of reading it from disk
+    pub fn add_source(&mut self, file: &str, source: &str) {
+        let lines = source.lines().map(str::to_string).collect();
+        self.sources.insert(file.to_string(), Some(lines));
+    }
+
+    pub fn render(&mut self, diagnostic: &Diagnostic) -> String {
+        let mut rendered = String::new();
+        let secondary_line = diagnostic.secondary.as_ref().map_or(0, |secondary| secondary.line);
+        let gutter_width = diagnostic.line.max(secondary_line).to_string().len();
+        let gutter = " ".repeat(gutter_width);
+        let color = diagnostic.severity.color();
+
+        // header -> error[H0004]: message
+        let _ = writeln!(
+            rendered,
+            "{}{}",
+            self.paint(color, &format!("{}[{}]", diagnostic.severity.name(), diagnostic.code)),
+            self.paint(BOLD, &format!(": {}", diagnostic.message))
+        );
+        // location -> file:line:col
+        if !diagnostic.file.is_empty() {
+            let location = if diagnostic.line > 0 {
+                format!("{}:{}:{}", diagnostic.file, diagnostic.line, diagnostic.column)
+            } else {
+                diagnostic.file.clone()
+            };
+            let _ = writeln!(rendered, "{}{} {}", gutter, self.paint(BLUE, "-->"), location);
+        }
+        // snippet -> the offending line with the bad token underlined
+        let mut has_snippet = self.snippet(
+            &mut rendered,
+            gutter_width,
+            (&diagnostic.file, diagnostic.line, diagnostic.column, diagnostic.width),
+            (color, '^', ""),
+        );
+        if let Some(secondary) = &diagnostic.secondary {
+            if has_snippet {
+                let _ = writeln!(rendered, "{} {}", gutter, self.paint(BLUE, "|"));
+            }
+            let location = format!("{}:{}:{}", secondary.file, secondary.line, secondary.column);
+            let _ = writeln!(rendered, "{}{} {}", gutter, self.paint(BLUE, ":::"), location);
+            has_snippet |= self.snippet(
+                &mut rendered,
+                gutter_width,
+                (&secondary.file, secondary.line, secondary.column, secondary.width),
+                (BLUE, '-', &secondary.label),
+            );
+        }
+        let has_notes = diagnostic.help.is_some() || diagnostic.note.is_some() || !diagnostic.included_from.is_empty();
+        if has_snippet && has_notes {
+            let _ = writeln!(rendered, "{} {}", gutter, self.paint(BLUE, "|"));
+        }
+        // `= note: included from Main.asm:2:1` for every file or macro on the way to this line
+        let included_from = diagnostic.included_from.iter().map(|site| {
+            Some(match &site.macro_name {
+                Some(name) => format!("in expansion of `{}` at {}:{}:{}", name, site.file, site.line, site.column),
+                None => format!("included from {}:{}:{}", site.file, site.line, site.column),
+            })
+        });
+        let notes = [("help", diagnostic.help.clone()), ("note", diagnostic.note.clone())]
+            .into_iter()
+            .chain(included_from.map(|text| ("note", text)));
+        for (title, text) in notes {
+            if let Some(text) = text {
+                let _ = writeln!(
+                    rendered,
+                    "{} {} {}",
+                    gutter,
+                    self.paint(BLUE, "="),
+                    self.paint(BOLD, &format!("{}: {}", title, text))
+                );
+            }
+        }
+        rendered
+    }
+
+    // writes one source line with `marker` under the given columns, followed by label
+    // returns false when the source line is not available
+    fn snippet(
+        &mut self,
+        rendered: &mut String,
+        gutter_width: usize,
+        (file, line, column, width): (&str, usize, usize, usize),
+        (color, marker, label): (&str, char, &str),
+    ) -> bool {
+        let Some(source_line) = self.source_line(file, line) else {
+            return false;
+        };
+        let (expanded, start, width) = expand_tabs(&source_line, column, width);
+        let gutter = " ".repeat(gutter_width);
+        let bar = self.paint(BLUE, "|");
+        let underline = marker.to_string().repeat(width);
+        let underline = if label.is_empty() {
+            underline
+        } else {
+            format!("{} {}", underline, label)
+        };
+        let _ = writeln!(rendered, "{} {}", gutter, bar);
+        let _ = writeln!(
+            rendered,
+            "{} {} {}",
+            self.paint(BLUE, &format!("{:>width$}", line, width = gutter_width)),
+            bar,
+            expanded
+        );
+        let _ = writeln!(
+            rendered,
+            "{} {} {}{}",
+            gutter,
+            bar,
+            " ".repeat(start),
+            self.paint(color, &underline)
+        );
+        true
+    }
+
+    fn source_line(&mut self, file: &str, line: usize) -> Option<String> {
+        if line == 0 {
+            return None;
+        }
+        let lines = self.sources.entry(file.to_string()).or_insert_with(|| {
+            fs::read_to_string(file)
+                .ok()
+                .map(|source| source.lines().map(str::to_string).collect())
+        });
+        lines.as_ref()?.get(line - 1).cloned()
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+// replaces tabs with spaces so the underline lines up with what the terminal shows
+// returns the expanded line, and the 0-based start and width of the underline in it
+fn expand_tabs(line: &str, column: usize, width: usize) -> (String, usize, usize) {
+    let mut expanded = String::new();
+    let mut start = None;
+    let mut end = None;
+    let first = column.max(1) - 1;
+    for (index, char) in line.chars().enumerate() {
+        if index == first {
+            start = Some(expanded.len());
+        }
+        if index == first + width {
+            end = Some(expanded.len());
+        }
+        if char == '\t' {
+            expanded.push_str(&" ".repeat(TAB_WIDTH));
+        } else {
+            expanded.push(char);
+        }
+    }
+    let start = start.unwrap_or(expanded.len());
+    let end = end.unwrap_or(expanded.len()).max(start + 1);
+    (expanded, start, end - start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Related;
+
+    #[test]
+    fn test_render_snippet() {
+        let mut renderer = Renderer::new(false);
+        renderer.add_source("Max.asm", "@R0\nD=M\n  0;JMPP // loop\n");
+        let error = AssembleError::new(ErrorKind::InvalidJump, "JMPP").at("Max.asm", 3, 5);
+        assert_eq!(
+            renderer.render(&Diagnostic::from(&error)),
+            "error[H0004]: invalid jump mnemonic `JMPP`\n \
+             --> Max.asm:3:5\n  \
+              |\n\
+             3 |   0;JMPP // loop\n  \
+              |     ^^^^\n  \
+              |\n  \
+              = help: valid jumps are JGT, JEQ, JGE, JLT, JNE, JLE and JMP\n"
+        );
+    }
+
+    #[test]
+    fn test_render_suggestion() {
+        let mut renderer = Renderer::new(false);
+        renderer.add_source("Max.asm", "0;JMPP");
+        let error = AssembleError::new(ErrorKind::InvalidJump, "JMPP")
+            .at("Max.asm", 1, 3)
+            .with_suggestion(Some("JMP"));
+        assert!(renderer
+            .render(&Diagnostic::from(&error))
+            .ends_with("  = help: did you mean `JMP`?\n"));
+    }
+
+    #[test]
+    fn test_render_warning() {
+        let mut renderer = Renderer::new(false);
+        renderer.add_source("Max.asm", "(UNUSED)");
+        let warning = Warning {
+            lint: crate::lint::Lint::UnusedLabel,
+            file: "Max.asm".to_string(),
+            line: 1,
+            column: 1,
+            text: "(UNUSED)".to_string(),
+            suggestion: None,
+            included_from: Box::new([]),
+        };
+        assert_eq!(
+            renderer.render(&Diagnostic::from(&warning)),
+            "warning[H1001]: label is never used `(UNUSED)`\n \
+             --> Max.asm:1:1\n  \
+              |\n\
+             1 | (UNUSED)\n  \
+              | ^^^^^^^^\n  \
+              |\n  \
+              = help: remove the label, or jump to it\n  \
+              = note: pass `-A unused-label` to silence this warning\n"
+        );
+    }
+
+    #[test]
+    fn test_render_secondary() {
+        let mut renderer = Renderer::new(false);
+        renderer.add_source("Loop.asm", "(LOOP)\n@LOOP\n0;JMP\n@i\nM=1\n@i\nM=0\n@i\nM=1\n(LOOP)");
+        let error = AssembleError::new(ErrorKind::DuplicateLabel, "LOOP")
+            .at("Loop.asm", 10, 2)
+            .with_related(Related {
+                message: "first defined here".to_string(),
+                file: "Loop.asm".to_string(),
+                line: 1,
+                column: 1,
+                text: "(LOOP)".to_string(),
+            });
+        assert_eq!(
+            renderer.render(&Diagnostic::from(&error)),
+            "error[H0002]: label defined more than once `LOOP`\n  \
+              --> Loop.asm:10:2\n   \
+               |\n\
+             10 | (LOOP)\n   \
+               |  ^^^^\n   \
+               |\n  \
+              ::: Loop.asm:1:1\n   \
+               |\n \
+              1 | (LOOP)\n   \
+               | ------ first defined here\n   \
+               |\n   \
+               = help: every label must have a unique name, rename one of them\n"
+        );
+    }
+
+    #[test]
+    fn test_render_related_note() {
+        let mut renderer = Renderer::new(false);
+        renderer.add_source("Screen.asm", "(SCREEN)");
+        let error = AssembleError::new(ErrorKind::PredefinedLabel, "SCREEN")
+            .at("Screen.asm", 1, 2)
+            .with_related(Related::note("`SCREEN` is predefined as address 16384"));
+        assert!(renderer
+            .render(&Diagnostic::from(&error))
+            .ends_with("  = note: `SCREEN` is predefined as address 16384\n"));
+    }
+
+    #[test]
+    fn test_render_without_source() {
+        let mut renderer = Renderer::new(false);
+        let error = AssembleError {
+            kind: ErrorKind::Io(std::io::ErrorKind::NotFound),
+            file: "Missing.asm".to_string(),
+            line: 0,
+            column: 0,
+            text: "No such file or directory".to_string(),
+            suggestion: None,
+            related: None,
+            included_from: Box::new([]),
+        };
+        assert_eq!(
+            renderer.render(&Diagnostic::from(&error)),
+            "error[H0013]: could not read or write file: No such file or directory\n --> Missing.asm\n"
+        );
+    }
+
+    #[test]
+    fn test_render_color() {
+        let mut renderer = Renderer::new(true);
+        renderer.add_source("Add.asm", "D=X");
+        let error = AssembleError::new(ErrorKind::InvalidComp, "X").at("Add.asm", 1, 3);
+        let rendered = renderer.render(&Diagnostic::from(&error));
+        assert!(rendered.starts_with("\x1b[1;31merror[H0001]\x1b[0m"));
+        assert!(rendered.contains("\x1b[1;31m^\x1b[0m"));
+    }
+
+    #[test]
+    fn test_json() {
+        let error = AssembleError::new(ErrorKind::InvalidJump, "JMPP")
+            .at("dir/Max \"1\".asm", 3, 5)
+            .with_suggestion(Some("JMP"));
+        assert_eq!(
+            Diagnostic::from(&error).to_json(),
+            "{\"type\":\"diagnostic\",\"severity\":\"error\",\"code\":\"H0004\",\
+             \"message\":\"invalid jump mnemonic `JMPP`\",\"file\":\"dir/Max \\\"1\\\".asm\",\
+             \"line\":3,\"column_start\":5,\"column_end\":9,\"suggestion\":\"JMP\",\
+             \"help\":\"did you mean `JMP`?\",\"note\":null,\"related\":null,\"included_from\":[]}"
+        );
+    }
+
+    #[test]
+    fn test_json_related_and_file_level() {
+        let error = AssembleError::new(ErrorKind::DuplicateLabel, "LOOP")
+            .at("Loop.asm", 4, 2)
+            .with_related(Related {
+                message: "first defined here".to_string(),
+                file: "Loop.asm".to_string(),
+                line: 1,
+                column: 1,
+                text: "(LOOP)".to_string(),
+            });
+        assert!(Diagnostic::from(&error).to_json().ends_with(
+            "\"related\":{\"message\":\"first defined here\",\"file\":\"Loop.asm\",\
+             \"line\":1,\"column_start\":1,\"column_end\":7},\"included_from\":[]}"
+        ));
+        let error = AssembleError::io("Missing.asm", &std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert!(Diagnostic::from(&error)
+            .to_json()
+            .contains("\"file\":\"Missing.asm\",\"line\":null,\"column_start\":null,\"column_end\":null"));
+    }
+
+    #[test]
+    fn test_include_chain() {
+        let mut renderer = Renderer::new(false);
+        renderer.add_source("lib/math.asm", "D=X");
+        let chain = [
+            IncludeSite { file: "lib/screen.asm".to_string(), line: 1, column: 1, macro_name: Some("SQUARE".to_string()) },
+            IncludeSite { file: "Main.asm".to_string(), line: 4, column: 3, macro_name: None },
+        ];
+        let error = AssembleError::new(ErrorKind::InvalidComp, "X")
+            .at("lib/math.asm", 1, 3)
+            .included_from(&chain);
+        let diagnostic = Diagnostic::from(&error);
+        assert!(renderer.render(&diagnostic).ends_with(
+            "  = note: in expansion of `SQUARE` at lib/screen.asm:1:1\n  \
+               = note: included from Main.asm:4:3\n"
+        ));
+        assert!(diagnostic.to_json().ends_with(
+            "\"included_from\":[{\"file\":\"lib/screen.asm\",\"line\":1,\"column\":1,\"macro\":\"SQUARE\"},\
+             {\"file\":\"Main.asm\",\"line\":4,\"column\":3,\"macro\":null}]}"
+        ));
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a\tb\u{1}\\"), "\"a\\tb\\u0001\\\\\"");
+    }
+
+    #[test]
+    fn test_expand_tabs() {
+        assert_eq!(expand_tabs("\tD;JMPP", 4, 4), ("    D;JMPP".to_string(), 6, 4));
+        // underline past the end of the line still shows a single caret
+        assert_eq!(expand_tabs("D=M", 4, 1), ("D=M".to_string(), 3, 1));
+    }
+}
@@ -0,0 +1,416 @@
+// errors produced while assembling a file
+// every error knows what went wrong (kind), where it happened (file, line, column)
+// and the offending piece of source text, so callers never have to parse a panic message
+
+use std::{error::Error, fmt, io};
+
+use crate::lint::Lint;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    Io(io::ErrorKind),
+    InvalidInstruction,
+    InvalidComp,
+    InvalidDest,
+    InvalidJump,
+    InvalidConstant,
+    ConstantOutOfRange,
+    InvalidLabel,
+    DuplicateLabel,
+    PredefinedLabel,
+    UndefinedLabel,
+    UnknownSymbol,
+    OutOfMemory,
+    // a token the grammar does not allow where it was written, e.g. the `M` in `D M=A`
+    UnexpectedToken,
+    // `/ 0` in a constant expression
+    DivisionByZero,
+    // a `/*` that is still open at the end of the file
+    UnterminatedComment,
+    // a symbol that breaks the naming rules, e.g. `1abc` or `(a-b)`
+    InvalidSymbol,
+    // more instructions than the ROM has words
+    ProgramTooLarge,
+    // a constant whose name is already a label, constant or predefined symbol, or the other way around
+    DuplicateSymbol,
+    // `.include` names a file that is in none of the places searched
+    IncludeNotFound,
+    // a file that includes itself, directly or through other files
+    IncludeCycle,
+    // a macro invoked with more or fewer arguments than it has parameters
+    MacroArguments,
+    // macros invoking each other deeper than the expansion limit, usually endless recursion
+    MacroRecursion,
+    // a `.macro` that is still open at the end of the file
+    UnterminatedMacro,
+    // a second macro with the name of one defined earlier
+    DuplicateMacro,
+    // an `.if` that is still open at the end of the file
+    UnterminatedConditional,
+    // macro expansions that add up to more lines than any program could use
+    ExpansionTooLarge,
+    // a lint denied with -D
+    Lint(Lint),
+}
+
+impl ErrorKind {
+    // a stable code for the kind, explained in full by `hack_assembler --explain <code>`
+    // codes are never reused or renumbered, so scripts can match on them
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::InvalidComp => "H0001",
+            ErrorKind::DuplicateLabel => "H0002",
+            ErrorKind::InvalidDest => "H0003",
+            ErrorKind::InvalidJump => "H0004",
+            ErrorKind::InvalidInstruction => "H0005",
+            ErrorKind::InvalidConstant => "H0006",
+            ErrorKind::ConstantOutOfRange => "H0007",
+            ErrorKind::InvalidLabel => "H0008",
+            ErrorKind::PredefinedLabel => "H0009",
+            ErrorKind::UndefinedLabel => "H0010",
+            ErrorKind::UnknownSymbol => "H0011",
+            ErrorKind::OutOfMemory => "H0012",
+            ErrorKind::Io(_) => "H0013",
+            ErrorKind::UnexpectedToken => "H0014",
+            ErrorKind::DivisionByZero => "H0015",
+            ErrorKind::UnterminatedComment => "H0016",
+            ErrorKind::InvalidSymbol => "H0017",
+            ErrorKind::ProgramTooLarge => "H0018",
+            ErrorKind::DuplicateSymbol => "H0019",
+            ErrorKind::IncludeNotFound => "H0020",
+            ErrorKind::IncludeCycle => "H0021",
+            ErrorKind::MacroArguments => "H0022",
+            ErrorKind::MacroRecursion => "H0023",
+            ErrorKind::UnterminatedMacro => "H0024",
+            ErrorKind::DuplicateMacro => "H0025",
+            ErrorKind::UnterminatedConditional => "H0026",
+            ErrorKind::ExpansionTooLarge => "H0027",
+            ErrorKind::Lint(lint) => lint.code(),
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            ErrorKind::Io(_) => "could not read or write file",
+            ErrorKind::InvalidInstruction => "invalid instruction",
+            ErrorKind::InvalidComp => "invalid comp mnemonic",
+            ErrorKind::InvalidDest => "invalid dest mnemonic",
+            ErrorKind::InvalidJump => "invalid jump mnemonic",
+            ErrorKind::InvalidConstant => "invalid constant",
+            ErrorKind::ConstantOutOfRange => "constant does not fit in an A-instruction",
+            ErrorKind::InvalidLabel => "malformed label",
+            ErrorKind::DuplicateLabel => "label defined more than once",
+            ErrorKind::PredefinedLabel => "label redefines a predefined symbol",
+            ErrorKind::UndefinedLabel => "jump to undefined label",
+            ErrorKind::UnknownSymbol => "symbol not registered in symbol table",
+            ErrorKind::OutOfMemory => "no free memory left for variable",
+            ErrorKind::UnexpectedToken => "unexpected token",
+            ErrorKind::DivisionByZero => "division by zero in constant expression",
+            ErrorKind::UnterminatedComment => "unterminated block comment",
+            ErrorKind::InvalidSymbol => "invalid symbol name",
+            ErrorKind::ProgramTooLarge => "program does not fit in ROM",
+            ErrorKind::DuplicateSymbol => "name is already defined",
+            ErrorKind::IncludeNotFound => "included file not found",
+            ErrorKind::IncludeCycle => "file includes itself",
+            ErrorKind::MacroArguments => "wrong number of macro arguments",
+            ErrorKind::MacroRecursion => "macro expansion nested too deeply",
+            ErrorKind::UnterminatedMacro => "macro definition without `.endm`",
+            ErrorKind::DuplicateMacro => "macro defined more than once",
+            ErrorKind::UnterminatedConditional => "conditional block without `.endif`",
+            ErrorKind::ExpansionTooLarge => "macro expansions are too large",
+            ErrorKind::Lint(lint) => lint.message(),
+        }
+    }
+
+    // a short hint on how to fix the error, shown under the source snippet
+    pub fn help(&self) -> Option<&'static str> {
+        match self {
+            ErrorKind::InvalidComp => Some("comp must be one of the 28 forms in the Hack spec, e.g. D+1, D&A or M-1"),
+            ErrorKind::InvalidDest => Some("valid destinations are M, D, MD, A, AM, AD and AMD"),
+            ErrorKind::InvalidJump => Some("valid jumps are JGT, JEQ, JGE, JLT, JNE, JLE and JMP"),
+            ErrorKind::InvalidConstant => Some("constants are written as 17, 0x4000, 0b1010, 0o17 or 'A'"),
+            ErrorKind::ConstantOutOfRange => Some("A-instructions load 15-bit values from 0 to 32767, pass --allow-raw-words to emit raw 16-bit words up to 65535"),
+            ErrorKind::InvalidLabel => Some("labels are written as (NAME) on a line of their own"),
+            ErrorKind::DuplicateLabel => Some("every label must have a unique name, rename one of them"),
+            ErrorKind::PredefinedLabel => Some("SP, LCL, ARG, THIS, THAT, R0-R15, SCREEN and KBD cannot be used as labels"),
+            ErrorKind::UndefinedLabel => Some("jump targets must be defined somewhere in the file as (NAME)"),
+            ErrorKind::Lint(lint) => Some(lint.help()),
+            ErrorKind::InvalidSymbol => Some("symbols are made of letters, digits, `_`, `.`, `$` and `:`, and cannot start with a digit"),
+            ErrorKind::IncludeNotFound => Some("paths are relative to the including file, add directories to search with -I"),
+            ErrorKind::IncludeCycle => Some("a file can only be included once along a chain of .include directives"),
+            ErrorKind::MacroArguments => Some("arguments are separated by commas, e.g. `COPY x, y`"),
+            ErrorKind::MacroRecursion => Some("a macro that invokes itself never stops expanding"),
+            ErrorKind::UnterminatedMacro => Some("end the macro body with `.endm` on a line of its own"),
+            ErrorKind::DuplicateMacro => Some("every macro must have a unique name, rename one of them"),
+            ErrorKind::ExpansionTooLarge => Some("a macro that invokes another one twice doubles its size, write a loop instead"),
+            ErrorKind::UnterminatedConditional => Some("end the block with `.endif`, every `.if`, `.ifdef` and `.ifndef` needs one"),
+            ErrorKind::DuplicateSymbol => Some("labels, constants and predefined symbols share one namespace, rename one of them"),
+            ErrorKind::ProgramTooLarge => Some("the Hack ROM holds 32768 instructions, addresses 0 to 32767"),
+            ErrorKind::UnterminatedComment => Some("close the comment with `*/`, block comments do not nest"),
+            ErrorKind::InvalidInstruction => Some("C instructions are written as dest=comp;jump, dest and jump are optional"),
+            _ => None,
+        }
+    }
+}
+
+// line and column are 1-based, 0 means "unknown" (e.g. the error came from a
+// component that never saw the source file, the assembler fills them in later)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleError {
+    pub kind: ErrorKind,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    // the closest valid mnemonic or known symbol, when the text looks like a typo of one
+    pub suggestion: Option<String>,
+    // another place the error refers to, e.g. where a duplicate label was first defined
+    pub related: Option<Box<Related>>,
+    // the `.include` directives that led to the file, innermost first
+    pub included_from: Box<[IncludeSite]>,
+}
+
+// an `.include` directive or a macro invocation, the place a line was pulled in from
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncludeSite {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    // the macro expanded here, None for an `.include`
+    pub macro_name: Option<String>,
+}
+
+// a secondary location attached to an error, a line of 0 means it has no place in the source
+// (e.g. a predefined symbol) and only the message is shown
+#[derive(Debug, Clone, PartialEq)]
+pub struct Related {
+    pub message: String,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+impl Related {
+    pub fn note(message: &str) -> Self {
+        Self {
+            message: message.to_string(),
+            file: String::new(),
+            line: 0,
+            column: 0,
+            text: String::new(),
+        }
+    }
+}
+
+impl AssembleError {
+    pub fn new(kind: ErrorKind, text: &str) -> Self {
+        Self {
+            kind,
+            file: String::new(),
+            line: 0,
+            column: 0,
+            text: text.to_string(),
+            suggestion: None,
+            related: None,
+            included_from: Box::new([]),
+        }
+    }
+
+    pub fn io(file: &str, error: &io::Error) -> Self {
+        Self {
+            kind: ErrorKind::Io(error.kind()),
+            file: file.to_string(),
+            line: 0,
+            column: 0,
+            text: error.to_string(),
+            suggestion: None,
+            related: None,
+            included_from: Box::new([]),
+        }
+    }
+
+    pub fn with_related(mut self, related: Related) -> Self {
+        self.related = Some(Box::new(related));
+        self
+    }
+
+    // errors in included files remember how the file was reached, unless they already do
+    pub fn included_from(mut self, chain: &[IncludeSite]) -> Self {
+        if self.included_from.is_empty() {
+            self.included_from = chain.into();
+        }
+        self
+    }
+
+    pub fn with_suggestion(mut self, suggestion: Option<&str>) -> Self {
+        self.suggestion = suggestion.map(str::to_string);
+        self
+    }
+
+    // attach a source location to an error raised without one
+    pub fn at(mut self, file: &str, line: usize, column: usize) -> Self {
+        self.file = file.to_string();
+        self.line = line;
+        self.column = column;
+        self
+    }
+
+    // point the error at its offending text inside `source`, which starts at `column` of `line`
+    // falls back to the start of `source` when the text cant be found (e.g. whitespace was stripped)
+    pub fn locate(self, file: &str, line: usize, source: &str, column: usize) -> Self {
+        let column = match source.find(self.text.as_str()) {
+            Some(byte_index) if !self.text.is_empty() => {
+                column + source[..byte_index].chars().count()
+            }
+            _ => column,
+        };
+        self.at(file, line, column)
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.file.is_empty() {
+            write!(f, "{}", self.file)?;
+            if self.line > 0 {
+                write!(f, ":{}:{}", self.line, self.column)?;
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "{}: `{}`", self.kind.message(), self.text)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (did you mean `{}`?)", suggestion)?;
+        }
+        if let Some(related) = &self.related {
+            write!(f, ", {}", related.message)?;
+            if related.line > 0 {
+                write!(f, " at {}:{}:{}", related.file, related.line, related.column)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error for AssembleError {}
+
+// gathers the errors of one run, once the cap is reached only errors earlier in the program
+// than the last one kept are still taken, in its place
+pub(crate) struct ErrorList {
+    // every error with the position it was found at, ordered by position
+    errors: Vec<(usize, AssembleError)>,
+    max_errors: Option<usize>,
+    position: usize,
+}
+
+impl ErrorList {
+    pub fn new(max_errors: Option<usize>) -> Self {
+        Self {
+            errors: Vec::new(),
+            max_errors,
+            position: 0,
+        }
+    }
+
+    // errors pushed from now on belong to the instruction with this index
+    pub fn set_position(&mut self, position: usize) {
+        self.position = position;
+    }
+
+    pub fn push(&mut self, error: AssembleError) {
+        // after the errors already found at the same position
+        let index = self.errors.partition_point(|(position, _)| *position <= self.position);
+        if self.is_full() {
+            if index == self.errors.len() {
+                return;
+            }
+            self.errors.pop();
+        }
+        self.errors.insert(index, (self.position, error));
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.max_errors
+            .is_some_and(|max_errors| self.errors.len() >= max_errors)
+    }
+
+    // whether an error found at position would be kept
+    pub fn accepts(&self, position: usize) -> bool {
+        !self.is_full() || self.errors.last().is_some_and(|(last, _)| position < *last)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    // already in program order, ties in the order they were found
+    pub fn into_vec(self) -> Vec<AssembleError> {
+        self.errors.into_iter().map(|(_, error)| error).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_with_location() {
+        let error = AssembleError::new(ErrorKind::InvalidJump, "JMPP").at("Max.asm", 3, 5);
+        assert_eq!(error.to_string(), "Max.asm:3:5: invalid jump mnemonic: `JMPP`");
+    }
+
+    #[test]
+    fn test_display_with_suggestion() {
+        let error = AssembleError::new(ErrorKind::InvalidJump, "JMPP").with_suggestion(Some("JMP"));
+        assert_eq!(error.to_string(), "invalid jump mnemonic: `JMPP` (did you mean `JMP`?)");
+    }
+
+    #[test]
+    fn test_display_without_location() {
+        let error = AssembleError::new(ErrorKind::InvalidComp, "D+X");
+        assert_eq!(error.to_string(), "invalid comp mnemonic: `D+X`");
+    }
+
+    #[test]
+    fn test_locate() {
+        let error = AssembleError::new(ErrorKind::InvalidJump, "JMPP").locate("Max.asm", 3, "0;JMPP", 3);
+        assert_eq!((error.line, error.column), (3, 5));
+        let error = AssembleError::new(ErrorKind::InvalidInstruction, "D=M=A").locate("Max.asm", 4, "D = M = A", 3);
+        assert_eq!((error.line, error.column), (4, 3));
+    }
+
+    #[test]
+    fn test_error_list_cap() {
+        let mut errors = ErrorList::new(Some(1));
+        errors.push(AssembleError::new(ErrorKind::InvalidComp, "X"));
+        errors.push(AssembleError::new(ErrorKind::InvalidDest, "Y"));
+        assert!(errors.is_full());
+        assert_eq!(errors.into_vec().len(), 1);
+    }
+
+    #[test]
+    fn test_error_list_cap_keeps_the_first_errors() {
+        let mut errors = ErrorList::new(Some(2));
+        for (position, text) in [(3, "A"), (5, "B"), (7, "C"), (1, "D"), (3, "E")] {
+            errors.set_position(position);
+            errors.push(AssembleError::new(ErrorKind::InvalidComp, text));
+        }
+        // `C` came too late, `B` made way for `D`, `E` ties with `A` but was found after it
+        assert!(!errors.accepts(3));
+        assert!(errors.accepts(2));
+        let texts: Vec<String> = errors.into_vec().into_iter().map(|error| error.text).collect();
+        assert_eq!(texts, ["D", "A"]);
+    }
+
+    #[test]
+    fn test_error_list_order() {
+        let mut errors = ErrorList::new(None);
+        errors.set_position(5);
+        errors.push(AssembleError::new(ErrorKind::InvalidComp, "X").at("Main.asm", 2, 3));
+        errors.set_position(1);
+        errors.push(AssembleError::new(ErrorKind::InvalidDest, "Y").at("lib.asm", 9, 1));
+        errors.push(AssembleError::new(ErrorKind::InvalidJump, "Z").at("lib.asm", 4, 1));
+        let texts: Vec<String> = errors.into_vec().into_iter().map(|error| error.text).collect();
+        assert_eq!(texts, ["Y", "Z", "X"]);
+    }
+}
@@ -0,0 +1,864 @@
+// long explanations for every diagnostic code, printed by `hack_assembler --explain H0002`
+// codes never change meaning once published: errors are H0xxx, lint warnings are H1xxx
+
+pub fn explanation(code: &str) -> Option<&'static str> {
+    let explanation = match code.to_uppercase().as_str() {
+        "H0001" => H0001,
+        "H0002" => H0002,
+        "H0003" => H0003,
+        "H0004" => H0004,
+        "H0005" => H0005,
+        "H0006" => H0006,
+        "H0007" => H0007,
+        "H0008" => H0008,
+        "H0009" => H0009,
+        "H0010" => H0010,
+        "H0011" => H0011,
+        "H0012" => H0012,
+        "H0013" => H0013,
+        "H0014" => H0014,
+        "H0015" => H0015,
+        "H0016" => H0016,
+        "H0017" => H0017,
+        "H0018" => H0018,
+        "H0019" => H0019,
+        "H0020" => H0020,
+        "H0021" => H0021,
+        "H0022" => H0022,
+        "H0023" => H0023,
+        "H0024" => H0024,
+        "H0025" => H0025,
+        "H0026" => H0026,
+        "H0027" => H0027,
+        "H1001" => H1001,
+        "H1002" => H1002,
+        "H1003" => H1003,
+        "H1004" => H1004,
+        "H1005" => H1005,
+        "H1006" => H1006,
+        _ => return None,
+    };
+    Some(explanation.trim())
+}
+
+const H0001: &str = r#"
+H0001: invalid comp mnemonic
+
+The comp part of a C instruction (`dest=comp;jump`) tells the ALU what to compute.
+The Hack spec defines exactly 28 comp forms, anything else cannot be encoded.
+
+    0    1    -1   D    A    !D   !A   -D   -A   D+1  A+1  D-1  A-1  D+A
+    D-A  A-D  D&A  D|A  M    !M   -M   M+1  M-1  D+M  D-M  M-D  D&M  D|M
+
+Wrong:
+
+    D=D+X      // there is no X register
+    D=A+M      // the ALU cannot combine A and M
+
+Right:
+
+    D=D+A
+    D=D+M
+"#;
+
+const H0002: &str = r#"
+H0002: label defined more than once
+
+A label `(NAME)` gives a name to the ROM address of the instruction after it.
+Each name can only point at one address, so a label can only be defined once.
+
+Wrong:
+
+    (LOOP)
+        @i
+        M=M+1
+    (LOOP)         // second definition, which one should @LOOP jump to?
+        @LOOP
+        0;JMP
+
+Right:
+
+    (LOOP)
+        @i
+        M=M+1
+    (LOOP_END)
+        @LOOP
+        0;JMP
+"#;
+
+const H0003: &str = r#"
+H0003: invalid dest mnemonic
+
+The dest part of a C instruction (`dest=comp;jump`) names the registers that receive
+the result. It is any combination of A, D and M, written in the order the Hack spec uses:
+
+    M    D    MD   A    AM   AD   AMD
+
+Wrong:
+
+    DM=D+1     // letters in the wrong order
+    X=D        // there is no X register
+
+Right:
+
+    MD=D+1
+    D=D
+"#;
+
+const H0004: &str = r#"
+H0004: invalid jump mnemonic
+
+The jump part of a C instruction (`dest=comp;jump`) compares the computed value with 0
+and jumps to the address in A when the comparison holds.
+
+    JGT  JEQ  JGE  JLT  JNE  JLE  JMP
+
+Wrong:
+
+    0;JMPP
+    D;JUMP
+
+Right:
+
+    0;JMP
+    D;JGT
+"#;
+
+const H0005: &str = r#"
+H0005: invalid instruction
+
+A C instruction is written `dest=comp;jump`, where `dest=` and `;jump` are optional.
+The line could not be split into those parts, usually because it has more than one `=`
+or more than one `;`.
+
+Wrong:
+
+    D=M=A
+    0;JMP;JMP
+
+Right:
+
+    D=M
+    0;JMP
+"#;
+
+const H0006: &str = r#"
+H0006: invalid constant
+
+A-instruction constants are written in decimal, hexadecimal (`0x`), binary (`0b`), octal
+(`0o`) or as a single character in quotes, which stands for its code in the Hack
+character set. The digits must all belong to the base and there must be at least one.
+
+Wrong:
+
+    @0x        // no digits
+    @0b102     // 2 is not a binary digit
+    @'AB'      // a character literal holds one character
+
+Right:
+
+    @0x4000    // 16384, the start of the screen
+    @0b1010    // 10
+    @0o17      // 15
+    @'A'       // 65
+"#;
+
+const H0007: &str = r#"
+H0007: constant does not fit in an A-instruction
+
+An A-instruction is a 16-bit word whose top bit is 0, which leaves 15 bits for the value:
+0 to 32767. A larger value would set the top bit and the CPU would decode the word as a
+C instruction instead of loading the value.
+
+Wrong:
+
+    @40000
+
+Right:
+
+    @32767
+
+If you really want to emit a raw 16-bit word, pass `--allow-raw-words`, which accepts
+values up to 65535.
+"#;
+
+const H0008: &str = r#"
+H0008: malformed label
+
+Labels are written as a name in parentheses on a line of their own.
+
+Wrong:
+
+    (LOOP
+    ()
+
+Right:
+
+    (LOOP)
+"#;
+
+const H0009: &str = r#"
+H0009: label redefines a predefined symbol
+
+SP, LCL, ARG, THIS, THAT, R0-R15, SCREEN and KBD are predefined RAM addresses. A label
+with one of these names would be ignored and every reference would keep using the RAM
+address, so it is rejected.
+
+Wrong:
+
+    (SCREEN)
+        @SCREEN
+        0;JMP
+
+Right:
+
+    (DRAW_SCREEN)
+        @DRAW_SCREEN
+        0;JMP
+"#;
+
+const H0010: &str = r#"
+H0010: jump to undefined label
+
+`@NAME` followed by a jump, where NAME is not a label anywhere in the file. Without this
+error NAME would become a new variable and the program would jump to its RAM address
+(16, 17, ...), which is almost always a misspelled label.
+
+Wrong:
+
+    (LOOP)
+        @LOOOP
+        0;JMP
+
+Right:
+
+    (LOOP)
+        @LOOP
+        0;JMP
+"#;
+
+const H0011: &str = r#"
+H0011: symbol not registered in symbol table
+
+Constants and the conditions of `.if` are evaluated while the file is read, so they can
+only use labels and constants defined above them. A symbol that is not defined yet, or
+anywhere, has no value to use.
+
+Wrong:
+
+    .equ LAST FIRST+10     // FIRST is only defined on the next line
+    .equ FIRST 16
+
+Right:
+
+    .equ FIRST 16
+    .equ LAST FIRST+10
+"#;
+
+const H0012: &str = r#"
+H0012: no free memory left for variable
+
+Every new variable takes the next free RAM address starting at 16. Variables end at
+16383, the screen memory map starts at 16384, so a program can use at most 16368 of them.
+The program declares more variables than that.
+
+Wrong:
+
+    @value1
+    M=0
+    ...
+    @value16369    // one more than fits
+    M=0
+
+Right: keep large tables in one block of RAM and index into it through a pointer.
+
+    @i
+    D=M
+    @table
+    A=D+M
+    M=0
+"#;
+
+const H0013: &str = r#"
+H0013: could not read or write file
+
+The input `.asm` file could not be read, or the `.hack` file could not be written to the
+`output/` directory. Check the path and the file permissions.
+
+Wrong:
+
+    $ hack_assembler Mult.asm           // the file is in input/
+
+Right:
+
+    $ hack_assembler input/Mult.asm
+"#;
+
+const H0014: &str = r#"
+H0014: unexpected token
+
+Whitespace separates words but never joins them, so every part of an instruction must be
+written as one word: the dest, the jump, each register or constant in the comp, and the
+operand of an A-instruction. The note under the error says what was expected instead.
+
+Wrong:
+
+    D M=A      // two destinations, did you mean MD?
+    @1 0       // two constants
+    (LOOP) D=M // a label must be on a line of its own
+    D=M#1      // `#` is not part of Hack assembly
+
+Right:
+
+    MD=A
+    @10
+    (LOOP)
+    D=M
+"#;
+
+const H0015: &str = r#"
+H0015: division by zero in constant expression
+
+A-instruction expressions are evaluated when the program is assembled, after every label
+and constant is known. A divisor that comes out as 0 has no value to load.
+
+Wrong:
+
+    .equ ROWS 0
+    @SCREEN/ROWS
+
+Right:
+
+    .equ ROWS 256
+    @SCREEN/ROWS
+"#;
+
+const H0016: &str = r#"
+H0016: unterminated block comment
+
+A block comment starts at `/*` and runs, across as many lines as it needs, up to the next
+`*/`. A comment that is never closed would swallow the rest of the file. Block comments do
+not nest: the first `*/` closes the comment, whatever `/*` came before it.
+
+Wrong:
+
+    /* multiply R0 by R1
+    @R0
+    D=M
+
+Right:
+
+    /* multiply R0 by R1 */
+    @R0
+    D=M
+"#;
+
+const H0017: &str = r#"
+H0017: invalid symbol name
+
+Symbols, both labels and variables, are made of ASCII letters, digits, `_`, `.`, `$` and
+`:`, and cannot start with a digit. Anything else is either a constant, an operator in an
+expression, or a typo.
+
+Wrong:
+
+    @1abc      // starts with a digit
+    (a-b)      // `-` cannot be part of a name
+    (@@)
+
+Right:
+
+    @abc1
+    (a_b)
+    (sys.init$ret:1)
+"#;
+
+const H0018: &str = r#"
+H0018: program does not fit in ROM
+
+The Hack ROM holds 32768 instructions. An A-instruction can only load addresses up to
+32767, so an instruction or label past the end of ROM could never be jumped to. The error
+points at the first instruction, or label, that lands outside of ROM.
+
+Wrong:
+
+    @SCREEN        // every word of the screen cleared on its own,
+    M=0            // 8192 of these take 16384 instructions
+    @SCREEN+1
+    M=0
+    ...
+
+Right: split the program, or generate less code for it, e.g. with a loop.
+
+    @SCREEN
+    D=A
+    @address
+    M=D
+    (CLEAR)
+        @address
+        A=M
+        M=0
+        @address
+        MD=M+1
+        @KBD
+        D=D-A
+        @CLEAR
+        D;JLT
+"#;
+
+const H0019: &str = r#"
+H0019: name is already defined
+
+A constant defined with `.equ NAME value` or `#define NAME value` shares its name space
+with labels and the predefined symbols. `@NAME` can only mean one thing, so a name can be
+a label or a constant, once, but not both.
+
+Wrong:
+
+    .equ ROWS 256
+    .equ ROWS 128     // defined twice
+    (ROWS)            // already a constant
+    .equ SCREEN 0     // SCREEN is predefined
+
+Right:
+
+    .equ ROWS 256
+    .equ HALF ROWS/2
+"#;
+
+const H0020: &str = r#"
+H0020: included file not found
+
+`.include "path"` is looked up relative to the directory of the file that contains the
+directive first, then in every directory passed with `-I`, in order. The note lists the
+places that were searched.
+
+Wrong:
+
+    .include "math.asm"        // math.asm is in lib/, next to this file
+
+Right:
+
+    .include "lib/math.asm"
+
+or keep the directive and assemble with `-I lib`.
+"#;
+
+const H0021: &str = r#"
+H0021: file includes itself
+
+Including a file pulls its text in where the directive is. A file that includes itself,
+directly or through other files, would never end. The note shows the chain of includes
+that leads back to the file.
+
+Wrong:
+
+    // a.asm
+    .include "b.asm"
+    // b.asm
+    .include "a.asm"
+
+Right: move what both files need into a third file and include that from both.
+
+    // a.asm and b.asm
+    .include "common.asm"
+"#;
+
+const H0022: &str = r#"
+H0022: wrong number of macro arguments
+
+A macro is invoked with one argument for every parameter of its `.macro` line, separated
+by commas. The note lists the parameters the macro expects.
+
+Wrong:
+
+    .macro COPY src, dst
+        @src
+        D=M
+        @dst
+        M=D
+    .endm
+        COPY x y
+
+Right:
+
+    .macro COPY src, dst
+        @src
+        D=M
+        @dst
+        M=D
+    .endm
+        COPY x, y
+"#;
+
+const H0023: &str = r#"
+H0023: macro expansion nested too deeply
+
+A macro body may invoke other macros, which are expanded in turn. Expansions nested
+more than 64 deep are stopped, since a macro that invokes itself, directly or through
+other macros, would otherwise expand forever.
+
+Wrong:
+
+    .macro LOOP_FOREVER
+        LOOP_FOREVER
+    .endm
+        LOOP_FOREVER
+
+Right: write the loop with a label and a jump inside the macro instead.
+
+    .macro LOOP_FOREVER
+    (LOOP)
+        @LOOP
+        0;JMP
+    .endm
+        LOOP_FOREVER
+"#;
+
+const H0024: &str = r#"
+H0024: macro definition without `.endm`
+
+Every line after `.macro` up to `.endm` is the body of the macro. A definition still
+open at the end of the file would swallow the rest of the program, so it is an error
+pointing at the `.macro`. Definitions cannot span files.
+
+Wrong:
+
+    .macro PUSH_D
+        @SP
+        AM=M+1
+        A=A-1
+        M=D
+
+Right:
+
+    .macro PUSH_D
+        @SP
+        AM=M+1
+        A=A-1
+        M=D
+    .endm
+"#;
+
+const H0025: &str = r#"
+H0025: macro defined more than once
+
+A macro name can only be defined once, the second definition would silently change what
+every invocation after it means. Macro names are separate from labels and constants.
+
+Wrong:
+
+    .macro CLEAR x
+        @x
+        M=0
+    .endm
+    .macro CLEAR x     // meant to set x to -1
+        @x
+        M=-1
+    .endm
+
+Right: give the second macro its own name.
+
+    .macro CLEAR x
+        @x
+        M=0
+    .endm
+    .macro FILL x
+        @x
+        M=-1
+    .endm
+"#;
+
+const H0026: &str = r#"
+H0026: conditional block without `.endif`
+
+Every `.if`, `.ifdef` and `.ifndef` starts a block that ends at its `.endif`, with an
+optional `.else` in between. A block still open at the end of the file, or at the end of
+the macro body it is in, is an error pointing at the directive that opened it. Blocks nest,
+so every inner block needs its own `.endif` too.
+
+Wrong:
+
+    .ifdef DEBUG
+        @TRACE
+        M=D
+    (END)
+
+Right:
+
+    .ifdef DEBUG
+        @TRACE
+        M=D
+    .endif
+    (END)
+"#;
+
+const H0027: &str = r#"
+H0027: macro expansions are too large
+
+The lines of every macro expansion are counted, all of them together may not add more
+than 131072 lines, four times the instructions that fit in ROM. A macro that invokes another one twice is twice
+its size, so a few levels of them grow the program past any size it could have. Expansion
+also stops once the program no longer fits in ROM.
+
+Wrong:
+
+    .macro CLEAR_1
+        M=0
+        A=A+1
+    .endm
+    .macro CLEAR_2
+        CLEAR_1
+        CLEAR_1
+    .endm
+    ...
+    .macro CLEAR_8192        // 16384 lines
+        CLEAR_4096
+        CLEAR_4096
+    .endm
+
+Right: write a loop that runs the body as many times as needed.
+
+    .macro CLEAR_SCREEN
+        @SCREEN
+        D=A
+        @address
+        M=D
+    (LOOP)
+        @address
+        A=M
+        M=0
+        @address
+        MD=M+1
+        @KBD
+        D=D-A
+        @LOOP
+        D;JLT
+    .endm
+        CLEAR_SCREEN
+"#;
+
+const H1001: &str = r#"
+H1001: label is never used (lint `unused-label`)
+
+A label that no `@NAME` refers to has no effect, it is usually left over from an edit or
+the reference to it is misspelled.
+
+Warns:
+
+    (UNUSED)
+    (END)
+        @END
+        0;JMP
+
+Fine:
+
+    (LOOP)
+        @LOOP
+        0;JMP
+"#;
+
+const H1002: &str = r#"
+H1002: variable is only referenced once (lint `single-use-variable`)
+
+A variable that is written but never read back, or read but never written, is usually a
+misspelled name of another variable.
+
+Warns:
+
+    @count
+    M=0
+    @cuont     // typo, allocates a second variable
+    M=M+1
+
+Fine:
+
+    @count
+    M=0
+    @count
+    M=M+1
+"#;
+
+const H1003: &str = r#"
+H1003: label is referenced but never jumped to (lint `label-never-jumped-to`)
+
+Labels name ROM addresses. Loading one into A without jumping usually means a variable
+was intended, since the value will be used as a RAM address.
+
+Warns:
+
+    @DATA
+    D=M        // reads RAM at the ROM address of DATA
+    (DATA)
+
+Fine:
+
+    @data
+    D=M
+"#;
+
+const H1004: &str = r#"
+H1004: unreachable instruction after unconditional jump (lint `unreachable-code`)
+
+The instruction after `0;JMP` (or any comp with JMP) can only run if something jumps to
+it, which needs a label.
+
+Warns:
+
+        @END
+        0;JMP
+        D=M        // never runs
+    (END)
+        @END
+        0;JMP
+
+Fine:
+
+        @END
+        0;JMP
+    (NEXT)
+        D=M
+    (END)
+        @END
+        0;JMP
+"#;
+
+const H1005: &str = r#"
+H1005: label differs from a predefined symbol only in case (lint `label-shadows-predefined`)
+
+Symbols are case sensitive, so `(screen)` is a different symbol from `SCREEN`, but the two
+are easy to mix up when reading the code.
+
+Warns:
+
+    (screen)
+
+Fine:
+
+    (DRAW_SCREEN)
+"#;
+
+const H1006: &str = r#"
+H1006: comp is not spelled the way the Hack spec does (lint `non-canonical-comp`)
+
+`+`, `&` and `|` are commutative, so `A+D`, `M|D` or `1+D` compute the same value as the
+spec's `D+A`, `D|M` and `D+1` and are assembled to the same word. This lint is allowed by
+default, pass `--strict` or `-W non-canonical-comp` to keep hand-written code in the
+spec's spelling.
+
+Warns:
+
+    D=A+D
+    M=1+M
+
+Fine:
+
+    D=D+A
+    M=M+1
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        assembler::{assemble_str_with_options, AssembleOptions},
+        error::ErrorKind,
+        lint::{Lint, LintLevel},
+    };
+
+    #[test]
+    fn test_every_code_is_explained() {
+        let kinds = [
+            ErrorKind::Io(std::io::ErrorKind::NotFound),
+            ErrorKind::InvalidInstruction,
+            ErrorKind::InvalidComp,
+            ErrorKind::InvalidDest,
+            ErrorKind::InvalidJump,
+            ErrorKind::InvalidConstant,
+            ErrorKind::ConstantOutOfRange,
+            ErrorKind::InvalidLabel,
+            ErrorKind::DuplicateLabel,
+            ErrorKind::PredefinedLabel,
+            ErrorKind::UndefinedLabel,
+            ErrorKind::UnknownSymbol,
+            ErrorKind::OutOfMemory,
+            ErrorKind::UnexpectedToken,
+            ErrorKind::DivisionByZero,
+            ErrorKind::UnterminatedComment,
+            ErrorKind::InvalidSymbol,
+            ErrorKind::ProgramTooLarge,
+            ErrorKind::DuplicateSymbol,
+            ErrorKind::IncludeNotFound,
+            ErrorKind::IncludeCycle,
+            ErrorKind::MacroArguments,
+            ErrorKind::MacroRecursion,
+            ErrorKind::UnterminatedMacro,
+            ErrorKind::DuplicateMacro,
+            ErrorKind::UnterminatedConditional,
+            ErrorKind::ExpansionTooLarge,
+        ];
+        let lints = Lint::ALL.into_iter().map(ErrorKind::Lint);
+        for kind in kinds.into_iter().chain(lints) {
+            let explanation = explanation(kind.code()).unwrap_or_else(|| panic!("{} is not explained", kind.code()));
+            // the first line repeats the message the diagnostic is printed with
+            assert!(explanation.starts_with(&format!("{}: {}", kind.code(), kind.message())));
+        }
+    }
+
+    // the code after `Right:`, every line indented by 4 spaces, up to the first line that is not
+    // the indented lines after a heading such as `Right:`
+    fn example(explanation: &str, heading: &str) -> Option<String> {
+        let mut lines = explanation.lines().skip_while(|line| !line.starts_with(heading)).skip(1);
+        let example: Vec<&str> = lines
+            .by_ref()
+            .take_while(|line| line.is_empty() || line.starts_with("    "))
+            .map(|line| line.get(4..).unwrap_or(""))
+            .collect();
+        let example = example.join("\n");
+        (!example.trim().is_empty()).then_some(example)
+    }
+
+    #[test]
+    fn test_right_examples_assemble() {
+        // the files the `.include` examples refer to
+        let dir = std::env::temp_dir().join("hack_assembler_explain");
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib/math.asm"), "D=D+A\n").unwrap();
+        std::fs::write(dir.join("common.asm"), "D=D+A\n").unwrap();
+        let name = dir.join("Example.asm").to_string_lossy().into_owned();
+        for number in 1..=27 {
+            let code = format!("H{:04}", number);
+            let explanation = explanation(&code).unwrap();
+            assert!(explanation.contains("\nWrong:\n"), "{} has no wrong example", code);
+            let example = example(explanation, "Right:").unwrap_or_else(|| panic!("{} has no right example", code));
+            // command lines rather than assembly
+            if example.trim_start().starts_with('$') {
+                continue;
+            }
+            let result = assemble_str_with_options(&name, &example, &AssembleOptions::default());
+            assert!(result.is_ok(), "the right example of {} does not assemble: {:?}", code, result);
+        }
+        // lints have code that warns and code that is fine, both have to assemble
+        for lint in Lint::ALL {
+            let explanation = explanation(lint.code()).unwrap();
+            let mut options = AssembleOptions::default();
+            options.lints.set(lint, LintLevel::Warn);
+            let warns = |heading: &str| {
+                let example = example(explanation, heading)
+                    .unwrap_or_else(|| panic!("{} has no `{}` example", lint.code(), heading));
+                let program = assemble_str_with_options(&name, &example, &options).unwrap_or_else(|errors| {
+                    panic!("the `{}` example of {} does not assemble: {:?}", heading, lint.code(), errors)
+                });
+                program.warnings.iter().any(|warning| warning.lint == lint)
+            };
+            assert!(warns("Warns:"), "the wrong example of {} does not warn", lint.code());
+            assert!(!warns("Fine:"), "the right example of {} warns", lint.code());
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(explanation("h0002"), explanation("H0002"));
+        assert!(explanation("H9999").is_none());
+    }
+}
@@ -0,0 +1,306 @@
+// constant expressions in A-instructions: @SCREEN+32*row, @END-1, @(KBD-SCREEN)/2
+// + and - bind looser than * and /, operators of the same precedence group to the left
+// symbols are only known once every label has been seen, so expressions that use them
+// are kept as a tree and evaluated in the second pass
+
+use std::fmt;
+
+use crate::{
+    error::{AssembleError, ErrorKind, Related},
+    interner::{Interner, Name},
+    lexer::{Token, TokenKind},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Operator {
+    pub(crate) fn from_token(kind: TokenKind) -> Option<Operator> {
+        match kind {
+            TokenKind::Plus => Some(Operator::Add),
+            TokenKind::Minus => Some(Operator::Subtract),
+            TokenKind::Star => Some(Operator::Multiply),
+            TokenKind::Slash => Some(Operator::Divide),
+            _ => None,
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Operator::Add | Operator::Subtract => 1,
+            Operator::Multiply | Operator::Divide => 2,
+        }
+    }
+
+    fn symbol(&self) -> char {
+        match self {
+            Operator::Add => '+',
+            Operator::Subtract => '-',
+            Operator::Multiply => '*',
+            Operator::Divide => '/',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Constant(u32),
+    Symbol(Name),
+    Binary {
+        operator: Operator,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+}
+
+// the value of a number or character literal: 17, 0x4000, 0b1010, 0o17 or 'A'
+// digits that dont fit in 32 bits are out of range like any other large constant
+// `1abc` without a base prefix is not a number at all but a symbol starting with a digit
+pub fn parse_constant(literal: &str) -> Result<u32, ErrorKind> {
+    if let Some(quoted) = literal.strip_prefix('\'') {
+        // the Hack character set is ASCII, e.g. 'A' is 65
+        return match quoted.strip_suffix('\'') {
+            Some(char) if char.len() == 1 => Ok(u32::from(char.as_bytes()[0])),
+            _ => Err(ErrorKind::InvalidConstant),
+        };
+    }
+    let (digits, radix) = match literal.get(..2) {
+        Some("0x" | "0X") => (&literal[2..], 16),
+        Some("0b" | "0B") => (&literal[2..], 2),
+        Some("0o" | "0O") => (&literal[2..], 8),
+        _ => (literal, 10),
+    };
+    if radix == 10 && digits.chars().any(|char| char.is_ascii_alphabetic()) {
+        return Err(ErrorKind::InvalidSymbol);
+    }
+    if digits.is_empty() || !digits.chars().all(|char| char.is_digit(radix)) {
+        return Err(ErrorKind::InvalidConstant);
+    }
+    u32::from_str_radix(digits, radix).map_err(|_| ErrorKind::ConstantOutOfRange)
+}
+
+// parses the tokens after `@`, errors carry the column of the offending token
+// but no file or line, the caller knows those
+pub fn parse(tokens: &[Token], interner: &mut Interner) -> Result<Expression, AssembleError> {
+    let mut parser = ExpressionParser {
+        tokens,
+        position: 0,
+        interner,
+    };
+    let expression = parser.expression(0)?;
+    match parser.peek() {
+        Some(extra) => Err(unexpected(extra, "expected an operator: +, -, * or /")),
+        None => Ok(expression),
+    }
+}
+
+fn unexpected(token: &Token, expected: &str) -> AssembleError {
+    AssembleError::new(ErrorKind::UnexpectedToken, token.text)
+        .at("", 0, token.span.start)
+        .with_related(Related::note(expected))
+}
+
+struct ExpressionParser<'a, 'b> {
+    tokens: &'b [Token<'a>],
+    position: usize,
+    interner: &'b mut Interner,
+}
+
+impl<'a, 'b> ExpressionParser<'a, 'b> {
+    fn peek(&self) -> Option<&'b Token<'a>> {
+        self.tokens.get(self.position)
+    }
+
+    // precedence climbing: parses operands joined by operators that bind tighter than `min_precedence`
+    fn expression(&mut self, min_precedence: u8) -> Result<Expression, AssembleError> {
+        let mut left = self.operand()?;
+        while let Some(operator) = self.peek().and_then(|token| Operator::from_token(token.kind)) {
+            if operator.precedence() <= min_precedence {
+                break;
+            }
+            self.position += 1;
+            let right = self.expression(operator.precedence())?;
+            left = Expression::Binary {
+                operator,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn operand(&mut self) -> Result<Expression, AssembleError> {
+        let Some(&token) = self.peek() else {
+            // the expression ended after an operator or `(`
+            let last = self.tokens[self.tokens.len() - 1];
+            return Err(unexpected(&last, "expected a constant, symbol or `(` after this"));
+        };
+        self.position += 1;
+        match token.kind {
+            TokenKind::Number | TokenKind::Char => parse_constant(token.text)
+                .map(Expression::Constant)
+                .map_err(|kind| AssembleError::new(kind, token.text).at("", 0, token.span.start)),
+            TokenKind::Identifier => Ok(Expression::Symbol(self.interner.intern(token.text))),
+            TokenKind::LeftParen => {
+                let inner = self.expression(0)?;
+                match self.peek() {
+                    Some(close) if close.kind == TokenKind::RightParen => {
+                        self.position += 1;
+                        Ok(inner)
+                    }
+                    Some(other) => Err(unexpected(other, "expected `)`")),
+                    None => Err(unexpected(&token, "this `(` is never closed")),
+                }
+            }
+            _ => Err(unexpected(&token, "expected a constant, symbol or `(`")),
+        }
+    }
+}
+
+impl Expression {
+    // every symbol the expression refers to, in the order they were written
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Expression::Constant(_) => Vec::new(),
+            Expression::Symbol(symbol) => vec![&**symbol],
+            Expression::Binary { left, right, .. } => {
+                let mut symbols = left.symbols();
+                symbols.extend(right.symbols());
+                symbols
+            }
+        }
+    }
+
+    // computes the value, `lookup` gives the address of a symbol
+    // intermediate results may be negative, e.g. in `(START-END)+8`,
+    // only the caller decides whether the final value fits in an instruction
+    pub fn evaluate(
+        &self,
+        lookup: &mut impl FnMut(&str) -> Result<u16, AssembleError>,
+    ) -> Result<i64, AssembleError> {
+        match self {
+            Expression::Constant(value) => Ok(i64::from(*value)),
+            Expression::Symbol(symbol) => lookup(symbol).map(i64::from),
+            Expression::Binary { operator, left, right } => {
+                let (left, right) = (left.evaluate(lookup)?, right.evaluate(lookup)?);
+                let value = match operator {
+                    Operator::Add => left.checked_add(right),
+                    Operator::Subtract => left.checked_sub(right),
+                    Operator::Multiply => left.checked_mul(right),
+                    Operator::Divide if right == 0 => {
+                        return Err(AssembleError::new(ErrorKind::DivisionByZero, &self.to_string()))
+                    }
+                    Operator::Divide => left.checked_div(right),
+                };
+                value.ok_or_else(|| AssembleError::new(ErrorKind::ConstantOutOfRange, &self.to_string()))
+            }
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Expression::Binary { operator, .. } => operator.precedence(),
+            _ => u8::MAX,
+        }
+    }
+}
+
+// writes the expression without spaces and with only the parentheses it needs
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Constant(value) => write!(f, "{}", value),
+            Expression::Symbol(symbol) => f.write_str(symbol),
+            Expression::Binary { operator, left, right } => {
+                let precedence = operator.precedence();
+                if left.precedence() < precedence {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                write!(f, "{}", operator.symbol())?;
+                // a - (b - c) and a / (b / c) need their parentheses, a + (b + c) does not
+                let grouping = matches!(operator, Operator::Subtract | Operator::Divide);
+                if right.precedence() < precedence || (grouping && right.precedence() == precedence) {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+
+    fn parse_str(source: &str) -> Result<Expression, AssembleError> {
+        parse(&tokenize(source), &mut Interner::new())
+    }
+
+    fn evaluate(source: &str) -> Result<i64, AssembleError> {
+        let symbols = [("SCREEN", 16384), ("KBD", 24576), ("row", 3), ("END", 10)];
+        parse_str(source)?.evaluate(&mut |symbol| {
+            symbols
+                .iter()
+                .find(|(name, _)| *name == symbol)
+                .map(|(_, value)| *value)
+                .ok_or_else(|| AssembleError::new(ErrorKind::UnknownSymbol, symbol))
+        })
+    }
+
+    #[test]
+    fn test_precedence_and_parentheses() {
+        assert_eq!(evaluate("SCREEN+32*row").unwrap(), 16384 + 96);
+        assert_eq!(evaluate("END-1").unwrap(), 9);
+        assert_eq!(evaluate("(KBD-SCREEN)/2").unwrap(), 4096);
+        assert_eq!(evaluate("KBD-SCREEN/2").unwrap(), 24576 - 8192);
+        assert_eq!(evaluate("10-4-3").unwrap(), 3);
+        assert_eq!(evaluate("100/10/5").unwrap(), 2);
+        assert_eq!(evaluate("2*(3+4)").unwrap(), 14);
+        assert_eq!(evaluate("0x10 + 'A' - 0b1").unwrap(), 80);
+    }
+
+    #[test]
+    fn test_display() {
+        for source in ["SCREEN+32*row", "(KBD-SCREEN)/2", "10-(4-3)", "2*(3+4)", "1+2+3", "8/(4/2)"] {
+            assert_eq!(parse_str(source).unwrap().to_string(), source);
+        }
+        assert_eq!(parse_str("( 1 + 2 ) + 3").unwrap().to_string(), "1+2+3");
+    }
+
+    #[test]
+    fn test_evaluation_errors() {
+        assert_eq!(evaluate("END/(row-3)").unwrap_err().kind, ErrorKind::DivisionByZero);
+        assert_eq!(evaluate("0xFFFFFFFF*0xFFFFFFFF*4").unwrap_err().kind, ErrorKind::ConstantOutOfRange);
+        assert_eq!(evaluate("ROW+1").unwrap_err().kind, ErrorKind::UnknownSymbol);
+        assert_eq!(evaluate("1-2").unwrap(), -1);
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let error = |source: &str| {
+            let error = parse_str(source).unwrap_err();
+            (error.kind, error.text, error.column)
+        };
+        assert_eq!(error("1 2"), (ErrorKind::UnexpectedToken, "2".to_string(), 3));
+        assert_eq!(error("(1+2"), (ErrorKind::UnexpectedToken, "(".to_string(), 1));
+        assert_eq!(error("(1+2 3)"), (ErrorKind::UnexpectedToken, "3".to_string(), 6));
+        assert_eq!(error("1+"), (ErrorKind::UnexpectedToken, "+".to_string(), 2));
+        assert_eq!(error("*2"), (ErrorKind::UnexpectedToken, "*".to_string(), 1));
+        assert_eq!(error("1+0xZZ"), (ErrorKind::InvalidConstant, "0xZZ".to_string(), 3));
+        assert_eq!(error("END+1abc"), (ErrorKind::InvalidSymbol, "1abc".to_string(), 5));
+    }
+
+    #[test]
+    fn test_symbols() {
+        assert_eq!(parse_str("SCREEN+32*(row-END)").unwrap().symbols(), vec!["SCREEN", "row", "END"]);
+    }
+}
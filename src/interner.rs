@@ -0,0 +1,45 @@
+// symbol names are interned: every `@LOOP` and `(LOOP)` in a file shares one allocation
+// instead of each instruction owning a copy of the name, which matters on generated
+// files where the same few hundred names are referenced millions of times
+
+use std::{collections::HashSet, rc::Rc};
+
+// an interned name, cloning it only bumps a reference count
+pub type Name = Rc<str>;
+
+#[derive(Debug, Default)]
+pub struct Interner {
+    names: HashSet<Name>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the shared copy of name, allocated the first time it is seen
+    pub fn intern(&mut self, name: &str) -> Name {
+        if let Some(interned) = self.names.get(name) {
+            return Rc::clone(interned);
+        }
+        let interned: Name = Rc::from(name);
+        self.names.insert(Rc::clone(&interned));
+        interned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_are_shared() {
+        let mut interner = Interner::new();
+        let first = interner.intern("LOOP");
+        let second = interner.intern(&String::from("LOOP"));
+        assert!(Rc::ptr_eq(&first, &second));
+        assert_eq!(&*first, "LOOP");
+        assert!(!Rc::ptr_eq(&first, &interner.intern("END")));
+        assert_eq!(interner.names.len(), 2);
+    }
+}
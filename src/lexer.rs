@@ -0,0 +1,402 @@
+// splits a line of Hack assembly into tokens, each with the columns it was written at
+// whitespace separates tokens and is dropped, `//` comments become a single token
+// `/* ... */` comments can span lines: the lexer only ever sees one line, so a comment still
+// open at the end of a line is reported by `in_block_comment` and handed to the next lexer
+// the lexer never fails: characters that cant start any token become `Unknown` tokens
+// and the parser decides what to do with them
+
+use std::{iter::Peekable, str::CharIndices};
+
+use crate::parser::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    // LOOP, D, JMP, R0, ponggame.0, sys.init$ret
+    Identifier,
+    // 17, 0x4000, 0b1010, 0o17, the parser checks the digits
+    Number,
+    // 'A'
+    Char,
+    // "lib/math.asm", the closing quote is missing if the line ended first
+    String,
+    At,
+    LeftParen,
+    RightParen,
+    Equals,
+    Semicolon,
+    // separates macro parameters and arguments
+    Comma,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Not,
+    And,
+    Or,
+    // `#define`, directives spelled with a dot such as `.equ` are identifiers
+    Directive,
+    // `// ...` up to the end of the line, or `/* ... */`, possibly only the part on this line
+    Comment,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    pub span: Span,
+    // byte offset of text in the line, so the parser can slice the line instead of copying it
+    pub offset: usize,
+}
+
+// symbols are made of ASCII letters, digits, `_`, `.`, `$` and `:`, and cant start with a digit
+fn is_identifier_start(char: char) -> bool {
+    char.is_ascii_alphabetic() || matches!(char, '_' | '.' | '$' | ':')
+}
+
+fn is_identifier_char(char: char) -> bool {
+    is_identifier_start(char) || char.is_ascii_digit()
+}
+
+pub struct Lexer<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    // 1-based column of the next char
+    column: usize,
+    // inside a `/* ... */` comment that has not been closed yet
+    in_block_comment: bool,
+    // whether `/*` opens a comment, otherwise it is `/` followed by `*`
+    block_comments: bool,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            chars: source.char_indices().peekable(),
+            column: 1,
+            in_block_comment: false,
+            block_comments: true,
+        }
+    }
+
+    // lexes `/* ... */` as a comment or, like the Nand2Tetris tools, only `//`
+    pub fn block_comments(mut self, enabled: bool) -> Self {
+        self.block_comments = enabled;
+        self
+    }
+
+    // lexes a line that starts inside a block comment opened on an earlier line
+    pub fn inside_block_comment(mut self, inside: bool) -> Self {
+        self.in_block_comment = inside;
+        self
+    }
+
+    // whether the line ended inside a block comment, only meaningful once every token has been read
+    pub fn in_block_comment(&self) -> bool {
+        self.in_block_comment
+    }
+
+    fn bump(&mut self) -> Option<(usize, char)> {
+        let next = self.chars.next()?;
+        self.column += 1;
+        Some(next)
+    }
+
+    // consumes chars while `accept` holds and returns the byte offset after the last one
+    fn eat_while(&mut self, accept: impl Fn(char) -> bool) -> usize {
+        while let Some(&(_, char)) = self.chars.peek() {
+            if !accept(char) {
+                break;
+            }
+            self.bump();
+        }
+        self.offset()
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.source.len(), |&(index, _)| index)
+    }
+
+    // consumes the rest of a block comment, up to and including `*/` or to the end of the line
+    fn eat_block_comment(&mut self) {
+        self.in_block_comment = true;
+        while let Some((_, char)) = self.bump() {
+            if char == '*' && self.chars.peek().is_some_and(|&(_, next)| next == '/') {
+                self.bump();
+                self.in_block_comment = false;
+                return;
+            }
+        }
+    }
+
+    // the token from byte offset start up to the next char
+    fn token(&mut self, kind: TokenKind, start: usize, start_column: usize) -> Token<'a> {
+        let end = self.offset();
+        Token {
+            kind,
+            text: &self.source[start..end],
+            span: Span {
+                start: start_column,
+                end: self.column,
+            },
+            offset: start,
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        self.eat_while(char::is_whitespace);
+        let start_column = self.column;
+        let start = self.offset();
+        if self.in_block_comment {
+            // a comment carried over from the previous line
+            self.chars.peek()?;
+            self.eat_block_comment();
+            return Some(self.token(TokenKind::Comment, start, start_column));
+        }
+        let (_, char) = self.bump()?;
+        let kind = match char {
+            '@' => TokenKind::At,
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            '=' => TokenKind::Equals,
+            ';' => TokenKind::Semicolon,
+            ',' => TokenKind::Comma,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '!' => TokenKind::Not,
+            '&' => TokenKind::And,
+            '|' => TokenKind::Or,
+            '/' if self.chars.peek().is_some_and(|&(_, next)| next == '/') => {
+                self.eat_while(|_| true);
+                TokenKind::Comment
+            }
+            '/' if self.block_comments && self.chars.peek().is_some_and(|&(_, next)| next == '*') => {
+                self.bump();
+                self.eat_block_comment();
+                TokenKind::Comment
+            }
+            '/' => TokenKind::Slash,
+            '*' => TokenKind::Star,
+            // letters are part of the number so `0x4000` is one token, `0xZZ` is a bad number
+            char if char.is_ascii_digit() => {
+                self.eat_while(|char| char.is_ascii_alphanumeric());
+                TokenKind::Number
+            }
+            // a single character between quotes, a lone `'` is left for the parser to reject
+            '\'' if self.source[start + 1..].chars().nth(1) == Some('\'') => {
+                self.bump();
+                self.bump();
+                TokenKind::Char
+            }
+            '"' => {
+                self.eat_while(|char| char != '"');
+                self.bump();
+                TokenKind::String
+            }
+            char if is_identifier_start(char) => {
+                self.eat_while(is_identifier_char);
+                TokenKind::Identifier
+            }
+            '#' if self.chars.peek().is_some_and(|&(_, next)| is_identifier_start(next)) => {
+                self.eat_while(is_identifier_char);
+                TokenKind::Directive
+            }
+            _ => TokenKind::Unknown,
+        };
+        Some(self.token(kind, start, start_column))
+    }
+}
+
+// every token on a line that does not start inside a block comment
+#[cfg(test)]
+pub fn tokenize(source: &str) -> Vec<Token<'_>> {
+    Lexer::new(source).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(source).into_iter().map(|token| (token.kind, token.text)).collect()
+    }
+
+    #[test]
+    fn test_c_instruction() {
+        assert_eq!(
+            kinds("MD=M+1;JGT"),
+            vec![
+                (TokenKind::Identifier, "MD"),
+                (TokenKind::Equals, "="),
+                (TokenKind::Identifier, "M"),
+                (TokenKind::Plus, "+"),
+                (TokenKind::Number, "1"),
+                (TokenKind::Semicolon, ";"),
+                (TokenKind::Identifier, "JGT"),
+            ]
+        );
+        assert_eq!(
+            kinds("D=!A|-1"),
+            vec![
+                (TokenKind::Identifier, "D"),
+                (TokenKind::Equals, "="),
+                (TokenKind::Not, "!"),
+                (TokenKind::Identifier, "A"),
+                (TokenKind::Or, "|"),
+                (TokenKind::Minus, "-"),
+                (TokenKind::Number, "1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_whitespace_separates_tokens() {
+        assert_eq!(
+            kinds("D M = A"),
+            vec![
+                (TokenKind::Identifier, "D"),
+                (TokenKind::Identifier, "M"),
+                (TokenKind::Equals, "="),
+                (TokenKind::Identifier, "A"),
+            ]
+        );
+        assert_eq!(
+            kinds("@ 1 0"),
+            vec![(TokenKind::At, "@"), (TokenKind::Number, "1"), (TokenKind::Number, "0")]
+        );
+    }
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(
+            kinds("@0x4000 @0b1010 @0o17 @'A' @'"),
+            vec![
+                (TokenKind::At, "@"),
+                (TokenKind::Number, "0x4000"),
+                (TokenKind::At, "@"),
+                (TokenKind::Number, "0b1010"),
+                (TokenKind::At, "@"),
+                (TokenKind::Number, "0o17"),
+                (TokenKind::At, "@"),
+                (TokenKind::Char, "'A'"),
+                (TokenKind::At, "@"),
+                (TokenKind::Unknown, "'"),
+            ]
+        );
+        assert_eq!(kinds("' '"), vec![(TokenKind::Char, "' '")]);
+        assert_eq!(kinds("1abc"), vec![(TokenKind::Number, "1abc")]);
+        assert_eq!(kinds("café"), vec![(TokenKind::Identifier, "caf"), (TokenKind::Unknown, "é")]);
+    }
+
+    #[test]
+    fn test_symbols_and_comments() {
+        assert_eq!(
+            kinds("(sys.init$ret:1) // return here"),
+            vec![
+                (TokenKind::LeftParen, "("),
+                (TokenKind::Identifier, "sys.init$ret:1"),
+                (TokenKind::RightParen, ")"),
+                (TokenKind::Comment, "// return here"),
+            ]
+        );
+        assert_eq!(kinds("#define WIDTH 32"), vec![
+            (TokenKind::Directive, "#define"),
+            (TokenKind::Identifier, "WIDTH"),
+            (TokenKind::Number, "32"),
+        ]);
+        assert_eq!(kinds(".include \"lib/math.asm\" \"open"), vec![
+            (TokenKind::Identifier, ".include"),
+            (TokenKind::String, "\"lib/math.asm\""),
+            (TokenKind::String, "\"open"),
+        ]);
+        assert_eq!(kinds("@x # ~"), vec![
+            (TokenKind::At, "@"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Unknown, "#"),
+            (TokenKind::Unknown, "~"),
+        ]);
+        assert_eq!(
+            kinds("@(KBD-SCREEN)/2*x"),
+            vec![
+                (TokenKind::At, "@"),
+                (TokenKind::LeftParen, "("),
+                (TokenKind::Identifier, "KBD"),
+                (TokenKind::Minus, "-"),
+                (TokenKind::Identifier, "SCREEN"),
+                (TokenKind::RightParen, ")"),
+                (TokenKind::Slash, "/"),
+                (TokenKind::Number, "2"),
+                (TokenKind::Star, "*"),
+                (TokenKind::Identifier, "x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_comments() {
+        assert_eq!(
+            kinds("D=M /* note */ ;JMP"),
+            vec![
+                (TokenKind::Identifier, "D"),
+                (TokenKind::Equals, "="),
+                (TokenKind::Identifier, "M"),
+                (TokenKind::Comment, "/* note */"),
+                (TokenKind::Semicolon, ";"),
+                (TokenKind::Identifier, "JMP"),
+            ]
+        );
+        // a comment left open carries over to the next line
+        let mut lexer = Lexer::new("@2 /* multiply");
+        let tokens: Vec<Token> = lexer.by_ref().collect();
+        assert_eq!(tokens[2].text, "/* multiply");
+        assert!(lexer.in_block_comment());
+        let mut lexer = Lexer::new("  still a comment").inside_block_comment(true);
+        assert_eq!(lexer.by_ref().map(|token| token.kind).collect::<Vec<_>>(), vec![TokenKind::Comment]);
+        assert!(lexer.in_block_comment());
+        let mut lexer = Lexer::new("by R1 */ D=A /* x */").inside_block_comment(true);
+        let tokens: Vec<Token> = lexer.by_ref().collect();
+        assert_eq!(tokens[0].text, "by R1 */");
+        assert_eq!((tokens[1].text, tokens[1].span.start), ("D", 10));
+        assert!(!lexer.in_block_comment());
+        // an empty line does not close the comment
+        let mut lexer = Lexer::new("").inside_block_comment(true);
+        assert_eq!(lexer.next(), None);
+        assert!(lexer.in_block_comment());
+        // `/*/` does not close the comment it opens
+        let mut lexer = Lexer::new("/*/ D=A");
+        assert_eq!(lexer.by_ref().count(), 1);
+        assert!(lexer.in_block_comment());
+        // with block comments turned off only `//` starts a comment
+        let tokens: Vec<TokenKind> = Lexer::new("D=A /* x */ // y").block_comments(false).map(|token| token.kind).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                TokenKind::Identifier,
+                TokenKind::Equals,
+                TokenKind::Identifier,
+                TokenKind::Slash,
+                TokenKind::Star,
+                TokenKind::Identifier,
+                TokenKind::Star,
+                TokenKind::Slash,
+                TokenKind::Comment,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let tokens = tokenize("\t@LOOP  // é");
+        assert_eq!(tokens[0].span, Span { start: 2, end: 3 });
+        assert_eq!(tokens[1].span, Span { start: 3, end: 7 });
+        assert_eq!(tokens[2].span, Span { start: 9, end: 13 });
+        // offsets count bytes, columns count characters
+        assert_eq!(tokens.iter().map(|token| token.offset).collect::<Vec<_>>(), vec![1, 2, 8]);
+        assert!(tokenize("   ").is_empty());
+    }
+}
@@ -1,4 +1,16 @@
+mod interner;
+mod macros;
+mod lexer;
+mod expression;
 mod parser;
+mod pseudo;
 mod translator;
 mod symbol_handler;
-pub mod assembler;
\ No newline at end of file
+mod suggest;
+pub mod assembler;
+pub mod error;
+pub mod diagnostic;
+pub mod lint;
+pub mod mnemonic;
+pub mod output;
+pub mod explain;
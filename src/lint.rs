@@ -0,0 +1,359 @@
+// warnings about code that assembles fine but is probably not what the author meant
+// every lint has a name and a level: allowed lints are not reported, warnings are reported
+// without touching the .hack output, denied lints fail the file like any other error
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::{AssembleError, ErrorKind, IncludeSite},
+    mnemonic::Jump,
+    parser::{Instruction, InstructionVariant},
+    symbol_handler::get_predefined_symbols,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedLabel,
+    SingleUseVariable,
+    LabelNeverJumpedTo,
+    UnreachableCode,
+    LabelShadowsPredefined,
+    NonCanonicalComp,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 6] = [
+        Lint::UnusedLabel,
+        Lint::SingleUseVariable,
+        Lint::LabelNeverJumpedTo,
+        Lint::UnreachableCode,
+        Lint::LabelShadowsPredefined,
+        Lint::NonCanonicalComp,
+    ];
+
+    // the name used on the command line, e.g. `-A unused-label`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedLabel => "unused-label",
+            Lint::SingleUseVariable => "single-use-variable",
+            Lint::LabelNeverJumpedTo => "label-never-jumped-to",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::LabelShadowsPredefined => "label-shadows-predefined",
+            Lint::NonCanonicalComp => "non-canonical-comp",
+        }
+    }
+
+    // the stable diagnostic code, lints are numbered from H1001
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lint::UnusedLabel => "H1001",
+            Lint::SingleUseVariable => "H1002",
+            Lint::LabelNeverJumpedTo => "H1003",
+            Lint::UnreachableCode => "H1004",
+            Lint::LabelShadowsPredefined => "H1005",
+            Lint::NonCanonicalComp => "H1006",
+        }
+    }
+
+    // the level a lint runs at unless the command line says otherwise
+    // strict style checks are opt-in, everything that is likely a bug warns
+    pub fn default_level(&self) -> LintLevel {
+        match self {
+            Lint::NonCanonicalComp => LintLevel::Allow,
+            _ => LintLevel::Warn,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            Lint::UnusedLabel => "label is never used",
+            Lint::SingleUseVariable => "variable is only referenced once",
+            Lint::LabelNeverJumpedTo => "label is referenced but never jumped to",
+            Lint::UnreachableCode => "unreachable instruction after unconditional jump",
+            Lint::LabelShadowsPredefined => "label differs from a predefined symbol only in case",
+            Lint::NonCanonicalComp => "comp is not spelled the way the Hack spec does",
+        }
+    }
+
+    pub fn help(&self) -> &'static str {
+        match self {
+            Lint::UnusedLabel => "remove the label, or jump to it",
+            Lint::SingleUseVariable => "a variable that is never read back is usually a misspelled name",
+            Lint::LabelNeverJumpedTo => "labels name ROM addresses, did you mean to use a variable?",
+            Lint::UnreachableCode => "add a label before this instruction so it can be jumped to",
+            Lint::LabelShadowsPredefined => "symbols are case sensitive, rename the label so it cannot be mistaken for the predefined one",
+            Lint::NonCanonicalComp => "write the operands of `+`, `&` and `|` in the order the Hack spec uses",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+// the level every lint runs at, see Lint::default_level for the defaults
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintLevels {
+    levels: HashMap<Lint, LintLevel>,
+}
+
+impl Default for LintLevels {
+    fn default() -> Self {
+        Self {
+            levels: Lint::ALL
+                .into_iter()
+                .map(|lint| (lint, lint.default_level()))
+                .collect(),
+        }
+    }
+}
+
+impl LintLevels {
+    pub fn set(&mut self, lint: Lint, level: LintLevel) {
+        self.levels.insert(lint, level);
+    }
+
+    pub fn set_all(&mut self, level: LintLevel) {
+        for lint in Lint::ALL {
+            self.set(lint, level);
+        }
+    }
+
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels.get(&lint).copied().unwrap_or(lint.default_level())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub lint: Lint,
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    // what to write instead of text, when there is a single obvious fix
+    pub suggestion: Option<String>,
+    // copied from the instruction the warning is about
+    pub included_from: Box<[IncludeSite]>,
+}
+
+impl Warning {
+    fn new(lint: Lint, instruction: &Instruction, text: &str) -> Self {
+        Self {
+            lint,
+            file: instruction.file.to_string(),
+            line: instruction.line_number,
+            column: instruction.span.start,
+            text: text.to_string(),
+            suggestion: None,
+            included_from: instruction.included_from.as_ref().into(),
+        }
+    }
+
+    // a denied lint is reported as an error
+    pub fn into_error(self) -> AssembleError {
+        AssembleError::new(ErrorKind::Lint(self.lint), &self.text)
+            .at(&self.file, self.line, self.column)
+            .with_suggestion(self.suggestion.as_deref())
+            .included_from(&self.included_from)
+    }
+}
+
+fn is_label(instruction: &Instruction) -> bool {
+    matches!(instruction.variant, InstructionVariant::Symbol { is_variable: false, .. })
+}
+
+// directives such as `.equ` can sit between instructions without being part of the code
+fn is_directive(instruction: &Instruction) -> bool {
+    matches!(instruction.variant, InstructionVariant::Constant { .. })
+}
+
+// runs every lint that is not allowed over a file that assembled without errors
+// `jumps_next` says for every instruction whether the next one taking a word jumps
+// `defines` are the constants given on the command line, they are not variables either
+pub(crate) fn check(
+    instructions: &[Instruction],
+    jumps_next: &[bool],
+    levels: &LintLevels,
+    defines: &[(String, u16)],
+) -> Vec<Warning> {
+    let enabled = |lint| levels.level(lint) != LintLevel::Allow;
+    if !Lint::ALL.into_iter().any(enabled) {
+        return Vec::new();
+    }
+    let predefined: HashSet<String> = get_predefined_symbols()
+        .into_iter()
+        .map(|(symbol, _)| symbol)
+        .collect();
+    // labels and references by their index into instructions
+    let mut labels: Vec<(&str, usize)> = Vec::new();
+    let mut constants: HashSet<&str> = defines.iter().map(|(name, _)| name.as_str()).collect();
+    // every @symbol reference, and whether the next instruction jumps to it
+    let mut references: HashMap<&str, Vec<(usize, bool)>> = HashMap::new();
+    let mut warnings: Vec<(usize, Warning)> = Vec::new();
+    let warning = |lint, index: usize| (index, Warning::new(lint, &instructions[index], &instructions[index].rep()));
+
+    for (index, instruction) in instructions.iter().enumerate() {
+        let jumps = jumps_next[index];
+        match &instruction.variant {
+            InstructionVariant::Symbol {
+                symbol,
+                is_variable: false,
+            } => labels.push((symbol, index)),
+            InstructionVariant::Symbol {
+                symbol,
+                is_variable: true,
+            } => {
+                references.entry(symbol).or_default().push((index, jumps));
+            }
+            InstructionVariant::Expression(expression) => {
+                for symbol in expression.symbols() {
+                    references.entry(symbol).or_default().push((index, jumps));
+                }
+            }
+            InstructionVariant::Constant { name, value } => {
+                constants.insert(name);
+                for symbol in value.symbols() {
+                    references.entry(symbol).or_default().push((index, false));
+                }
+            }
+            InstructionVariant::C { jmp, commuted, .. } => {
+                // anything straight after an unconditional jump can only be reached through a label
+                if *jmp == Jump::JMP && enabled(Lint::UnreachableCode) {
+                    let unreachable = instructions[index + 1..]
+                        .iter()
+                        .position(|next| !is_directive(next))
+                        .map(|offset| index + 1 + offset)
+                        .filter(|next| !is_label(&instructions[*next]));
+                    if let Some(unreachable) = unreachable {
+                        warnings.push(warning(Lint::UnreachableCode, unreachable));
+                    }
+                }
+                if *commuted && enabled(Lint::NonCanonicalComp) {
+                    let (index, mut warning) = warning(Lint::NonCanonicalComp, index);
+                    warning.suggestion = Some(instruction.canonical_rep());
+                    warnings.push((index, warning));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let label_names: HashSet<&str> = labels.iter().map(|(label, _)| *label).collect();
+    for &(label, index) in &labels {
+        // labels can never equal a predefined symbol, but `(screen)` next to `@SCREEN` is easy to misread,
+        // every predefined symbol is ASCII so no label needs to be uppercased to compare
+        if enabled(Lint::LabelShadowsPredefined) && predefined.iter().any(|symbol| symbol.eq_ignore_ascii_case(label)) {
+            warnings.push(warning(Lint::LabelShadowsPredefined, index));
+        }
+        match references.get(label) {
+            None if enabled(Lint::UnusedLabel) => warnings.push(warning(Lint::UnusedLabel, index)),
+            Some(uses) if enabled(Lint::LabelNeverJumpedTo) && !uses.iter().any(|(_, jumps)| *jumps) => {
+                warnings.push(warning(Lint::LabelNeverJumpedTo, index))
+            }
+            _ => {}
+        }
+    }
+    if enabled(Lint::SingleUseVariable) {
+        for (symbol, uses) in &references {
+            let defined = label_names.contains(symbol) || constants.contains(symbol) || predefined.contains(*symbol);
+            if uses.len() == 1 && !defined {
+                let (index, _) = uses[0];
+                warnings.push(warning(Lint::SingleUseVariable, index));
+            }
+        }
+    }
+
+    // in the order the instructions were read
+    warnings.sort_by_key(|(index, warning)| (*index, warning.column));
+    warnings.into_iter().map(|(_, warning)| warning).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{jumps_next, Parser};
+
+    fn lint(source: &str, levels: &LintLevels) -> Vec<(Lint, usize)> {
+        let mut parser = Parser::for_file("Lint.asm");
+        let instructions: Vec<Instruction> = source
+            .lines()
+            .filter_map(|line| parser.parse_line(line).unwrap())
+            .collect();
+        check(&instructions, &jumps_next(&instructions), levels, &[("DEBUG".to_string(), 1)])
+            .into_iter()
+            .map(|warning| (warning.lint, warning.line))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_program() {
+        let source = "@R0\nD=M\n@n\nM=D\n(LOOP)\n@n\nMD=M-1\n@LOOP\nD;JGT\n(END)\n@END\n0;JMP";
+        assert_eq!(lint(source, &LintLevels::default()), vec![]);
+    }
+
+    #[test]
+    fn test_every_lint() {
+        let source = "(UNUSED)\n@counter\nM=1\n@DATA\nD=A\n(DATA)\n(screen)\n@screen\n0;JMP\nD=M\n(END)\n@END\n0;JMP";
+        assert_eq!(
+            lint(source, &LintLevels::default()),
+            vec![
+                (Lint::UnusedLabel, 1),
+                (Lint::SingleUseVariable, 2),
+                (Lint::LabelNeverJumpedTo, 6),
+                (Lint::LabelShadowsPredefined, 7),
+                (Lint::UnreachableCode, 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expressions_reference_symbols() {
+        let source = "(TABLE)\n@TABLE+3\nD=M\n(END)\n@END-1\n0;JMP";
+        assert_eq!(lint(source, &LintLevels::default()), vec![(Lint::LabelNeverJumpedTo, 1)]);
+    }
+
+    #[test]
+    fn test_constants_are_not_variables() {
+        // a constant used once is fine, and a directive does not make the code after a jump reachable
+        let source = ".equ ROWS 256\n@ROWS\nD=A\n(END)\n@END\n0;JMP\n.equ LAST END\nD=M";
+        assert_eq!(lint(source, &LintLevels::default()), vec![(Lint::UnreachableCode, 8)]);
+        assert!(lint("@DEBUG\nD=A", &LintLevels::default()).is_empty());
+    }
+
+    #[test]
+    fn test_non_canonical_comp_is_opt_in() {
+        let source = "@2\nD=A\n@3\nD=A+D\nM=1+M";
+        assert_eq!(lint(source, &LintLevels::default()), vec![]);
+        let mut levels = LintLevels::default();
+        levels.set(Lint::NonCanonicalComp, LintLevel::Warn);
+        assert_eq!(
+            lint(source, &levels),
+            vec![(Lint::NonCanonicalComp, 4), (Lint::NonCanonicalComp, 5)]
+        );
+    }
+
+    #[test]
+    fn test_allowed_lints_are_not_reported() {
+        let mut levels = LintLevels::default();
+        levels.set(Lint::UnusedLabel, LintLevel::Allow);
+        assert_eq!(lint("(UNUSED)\n@counter\nM=1", &levels), vec![(Lint::SingleUseVariable, 2)]);
+        levels.set_all(LintLevel::Allow);
+        assert_eq!(lint("(UNUSED)\n@counter\nM=1", &levels), vec![]);
+    }
+
+    #[test]
+    fn test_lint_names() {
+        for lint in Lint::ALL {
+            assert_eq!(Lint::from_name(lint.name()), Some(lint));
+        }
+        assert_eq!(Lint::from_name("warnings"), None);
+    }
+}
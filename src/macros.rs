@@ -0,0 +1,206 @@
+// `.macro NAME param, param ... .endm` definitions and their expansion
+// the parser records the body lines of a definition as they were written, every invocation
+// substitutes the arguments for the parameters and gives the labels defined in the body
+// a name of their own, then the expanded lines are parsed like any other line
+
+use std::rc::Rc;
+
+use crate::{
+    expression::Operator,
+    interner::Name,
+    lexer::{Lexer, Token, TokenKind},
+};
+
+// how deep invocations inside macro bodies may nest before the expansion is given up on
+pub const MAX_EXPANSION_DEPTH: usize = 64;
+
+// how many lines all expansions together may add, invocations and comments included, four
+// times the instructions that fit in ROM, macros invoking each other twice would otherwise
+// double the program at every level
+pub const MAX_EXPANDED_LINES: usize = 131072;
+
+#[derive(Debug)]
+pub struct Macro {
+    pub name: Name,
+    pub params: Vec<Name>,
+    // where `.macro` was written, the body starts on the next line
+    pub file: Rc<str>,
+    pub line_number: usize,
+    pub column: usize,
+    body: Vec<String>,
+    // labels defined in the body, renamed in every expansion so each one gets its own
+    locals: Vec<Name>,
+}
+
+// a line of a macro body with the arguments substituted, or a line of the expansion
+// of a pseudo-instruction
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExpandedLine {
+    pub text: String,
+    // the column in the source line every char of text came from, macro bodies add the column after the line
+    columns: Vec<usize>,
+}
+
+impl ExpandedLine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // appends text that was not written in the source, it points at column
+    pub fn push(mut self, text: &str, column: usize) -> Self {
+        self.text.push_str(text);
+        self.columns.extend(text.chars().map(|_| column));
+        self
+    }
+
+    // appends text copied from the source line, starting at column
+    pub fn push_source(mut self, text: &str, column: usize) -> Self {
+        self.text.push_str(text);
+        self.columns.extend((column..).take(text.chars().count()));
+        self
+    }
+
+    // the column in the source line that a column of the expanded text came from
+    pub fn original_column(&self, column: usize) -> usize {
+        match self.columns.get(column.saturating_sub(1)) {
+            Some(&original) => original,
+            None => self.columns.last().map_or(column, |last| last + column - self.columns.len()),
+        }
+    }
+}
+
+impl Macro {
+    pub fn new(name: Name, params: Vec<Name>, file: Rc<str>, line_number: usize, column: usize) -> Self {
+        Self {
+            name,
+            params,
+            file,
+            line_number,
+            column,
+            body: Vec::new(),
+            locals: Vec::new(),
+        }
+    }
+
+    // adds a line to the body, label is the label the line defines, if any
+    pub fn push_line(&mut self, line: &str, label: Option<Name>) {
+        if let Some(label) = label {
+            self.locals.push(label);
+        }
+        self.body.push(line.to_string());
+    }
+
+    // the body with args in place of the parameters, `expansion` numbers the invocation
+    // so the local labels of every expansion are distinct, `(LOOP)` becomes `(LOOP$COPY.3)`
+    pub fn expand(&self, args: &[String], expansion: usize) -> Vec<ExpandedLine> {
+        let locals: Vec<String> = self
+            .locals
+            .iter()
+            .map(|label| format!("{}${}.{}", label, self.name, expansion))
+            .collect();
+        // `1+2` next to an operator is pasted in as `(1+2)`, so `@x*2` still doubles all of it
+        let grouped: Vec<Option<String>> = args.iter().map(|arg| is_compound(arg).then(|| format!("({})", arg))).collect();
+        let substitute = |tokens: &[Token], index: usize| -> Option<&str> {
+            let token = &tokens[index];
+            if token.kind != TokenKind::Identifier {
+                return None;
+            }
+            if let Some(param) = self.params.iter().position(|param| &**param == token.text) {
+                let is_operator = |token: Option<&Token>| token.is_some_and(|token| Operator::from_token(token.kind).is_some());
+                let before = index.checked_sub(1).map(|before| &tokens[before]);
+                let beside_operator = is_operator(before) || is_operator(tokens.get(index + 1));
+                return match &grouped[param] {
+                    Some(grouped) if beside_operator => Some(grouped),
+                    _ => Some(&args[param]),
+                };
+            }
+            let local = self.locals.iter().position(|label| &**label == token.text)?;
+            Some(&locals[local])
+        };
+        let mut in_block_comment = false;
+        let mut expanded = Vec::with_capacity(self.body.len());
+        for line in &self.body {
+            let mut lexer = Lexer::new(line).inside_block_comment(in_block_comment);
+            let tokens: Vec<Token> = lexer.by_ref().collect();
+            in_block_comment = lexer.in_block_comment();
+            let mut text = String::with_capacity(line.len());
+            let mut columns = Vec::with_capacity(line.len() + 1);
+            // byte offset and column of the first char not copied yet
+            let (mut copied, mut column) = (0, 1);
+            for (index, token) in tokens.iter().enumerate() {
+                let Some(replacement) = substitute(&tokens, index) else {
+                    continue;
+                };
+                for char in line[copied..token.offset].chars() {
+                    text.push(char);
+                    columns.push(column);
+                    column += 1;
+                }
+                // a substituted name points at the parameter it replaced
+                text.push_str(replacement);
+                columns.extend(replacement.chars().map(|_| token.span.start));
+                copied = token.offset + token.text.len();
+                column = token.span.end;
+            }
+            for char in line[copied..].chars() {
+                text.push(char);
+                columns.push(column);
+                column += 1;
+            }
+            columns.push(column);
+            expanded.push(ExpandedLine { text, columns });
+        }
+        expanded
+    }
+}
+
+// whether an argument is more than one token, `1+2` rather than `x` or `17`
+fn is_compound(arg: &str) -> bool {
+    Lexer::new(arg).filter(|token| token.kind != TokenKind::Comment).nth(1).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copy_macro() -> Macro {
+        let mut definition = Macro::new("COPY".into(), vec!["src".into(), "dst".into()], "Main.asm".into(), 1, 1);
+        for line in ["(LOOP) // src", "  @src", "  D=M", "  @dst+1 /* dst", "dst */ M=D", "@LOOP"] {
+            let label = line.starts_with("(LOOP)").then(|| "LOOP".into());
+            definition.push_line(line, label);
+        }
+        definition
+    }
+
+    #[test]
+    fn test_expand() {
+        let expanded = copy_macro().expand(&["x".to_string(), "SCREEN+32".to_string()], 3);
+        let lines: Vec<&str> = expanded.iter().map(|line| line.text.as_str()).collect();
+        // parameters are only replaced in code, not in comments
+        assert_eq!(
+            lines,
+            vec!["(LOOP$COPY.3) // src", "  @x", "  D=M", "  @(SCREEN+32)+1 /* dst", "dst */ M=D", "@LOOP$COPY.3"]
+        );
+        let line = &expanded[3];
+        // `+1` comes after the 11 chars of `(SCREEN+32)`, that replaced the 3 of `dst`
+        assert_eq!(line.original_column(15), 7);
+        assert_eq!(line.original_column(4), 4);
+        assert_eq!(line.original_column(5), 4);
+        assert_eq!(line.original_column(line.text.chars().count() + 2), 17);
+    }
+
+    #[test]
+    fn test_expand_groups_compound_args() {
+        let mut definition = Macro::new("DBL".into(), vec!["x".into()], "Main.asm".into(), 1, 1);
+        for line in ["@x*2", "@x", "D=x", "INNER 2*x, x"] {
+            definition.push_line(line, None);
+        }
+        let lines = |args: &[&str]| -> Vec<String> {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            definition.expand(&args, 1).into_iter().map(|line| line.text).collect()
+        };
+        // only an argument of several tokens next to an operator gets parentheses
+        assert_eq!(lines(&["1+2"]), vec!["@(1+2)*2", "@1+2", "D=1+2", "INNER 2*(1+2), 1+2"]);
+        assert_eq!(lines(&["SIZE"]), vec!["@SIZE*2", "@SIZE", "D=SIZE", "INNER 2*SIZE, SIZE"]);
+    }
+}
@@ -1,28 +1,260 @@
-use std::env;
-use hack_assembler::assembler;
+use std::{
+    env,
+    io::{self, IsTerminal},
+    path::PathBuf,
+    process,
+};
+use hack_assembler::{
+    assembler::{self, AssembleOptions, CommentStyle},
+    diagnostic::{json_summary, Diagnostic, Renderer, Severity},
+    explain,
+    lint::{Lint, LintLevel},
+};
 
-fn main() {
-    const MAX_NO_OF_FILES: u16 = 10;
-    let args: Vec<String> = env::args().collect();
-    // args.len() - 1, because the first argument is a reference to the target program
-    if args.len() - 1 > MAX_NO_OF_FILES.into() {
-        panic!(
+const MAX_NO_OF_FILES: usize = 10;
+
+// how errors and warnings are printed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MessageFormat {
+    // rustc-style text with source snippets
+    Human,
+    // one JSON object per message and a final summary object, for editors and CI
+    Json,
+}
+
+struct Args {
+    files: Vec<String>,
+    options: AssembleOptions,
+    message_format: MessageFormat,
+    // `--explain CODE` prints the long explanation of a diagnostic code instead of assembling
+    explain: Option<String>,
+}
+
+// applies `-W`, `-A` or `-D` to a lint name, `warnings` means every lint that is not allowed,
+// so `-D warnings` does not switch on opt-in lints like non-canonical-comp
+fn set_lint_level(options: &mut AssembleOptions, name: &str, level: LintLevel) -> Result<(), String> {
+    if name == "warnings" {
+        if level == LintLevel::Allow {
+            options.lints.set_all(level);
+        }
+        for lint in Lint::ALL {
+            if options.lints.level(lint) != LintLevel::Allow {
+                options.lints.set(lint, level);
+            }
+        }
+        return Ok(());
+    }
+    match Lint::from_name(name) {
+        Some(lint) => {
+            options.lints.set(lint, level);
+            Ok(())
+        }
+        None => {
+            let names: Vec<&str> = Lint::ALL.iter().map(Lint::name).collect();
+            Err(format!("Unknown lint: {}, expected one of: warnings, {}", name, names.join(", ")))
+        }
+    }
+}
+
+// splits the command line into the .asm files to assemble and the assembler options
+// accepts `--max-errors N` and `--max-errors=N` anywhere on the command line,
+// `-W`/`-A`/`-D <lint>` (or `-Wlint`) to warn about, allow or deny a lint,
+// `--allow-raw-words` to accept A-instruction constants up to 65535,
+// `-I <dir>` (or `-Idir`) to search dir for `.include` files,
+// `--pseudo` to accept pseudo-instructions such as `goto LABEL`,
+// `--listing` to also write output/XXX.lst with every instruction and its word,
+// `-D NAME=value` (or `-DNAME`, which is 1) to define a constant for `.if` and `.ifdef`,
+// `-D` followed by `warnings` or a lint name still denies that lint,
+// `--strict` to warn about comps not spelled the way the Hack spec does (`A+D` for `D+A`),
+// `--message-format human|json` to pick how messages are printed,
+// `--comment-style line|block` to accept only `//` comments or `/* */` ones too,
+// and `--explain CODE` to describe a diagnostic code, which needs no files
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut files = Vec::new();
+    let mut options = AssembleOptions::default();
+    let mut message_format = MessageFormat::Human;
+    let mut explain = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--max-errors" || arg.starts_with("--max-errors=") {
+            let value = match arg.split_once('=') {
+                Some((_, value)) => value,
+                None => args.next().ok_or("Expected a number after --max-errors")?,
+            };
+            let max_errors = value
+                .parse::<usize>()
+                .ok()
+                .filter(|max_errors| *max_errors > 0)
+                .ok_or(format!("Invalid value for --max-errors: {}", value))?;
+            options.max_errors = Some(max_errors);
+        } else if arg == "--message-format" || arg.starts_with("--message-format=") {
+            let value = match arg.split_once('=') {
+                Some((_, value)) => value,
+                None => args.next().ok_or("Expected human or json after --message-format")?,
+            };
+            message_format = match value {
+                "human" => MessageFormat::Human,
+                "json" => MessageFormat::Json,
+                _ => return Err(format!("Invalid value for --message-format: {}, expected human or json", value)),
+            };
+        } else if arg == "--comment-style" || arg.starts_with("--comment-style=") {
+            let value = match arg.split_once('=') {
+                Some((_, value)) => value,
+                None => args.next().ok_or("Expected line or block after --comment-style")?,
+            };
+            options.comment_style = match value {
+                "line" => CommentStyle::Line,
+                "block" => CommentStyle::Block,
+                _ => return Err(format!("Invalid value for --comment-style: {}, expected line or block", value)),
+            };
+        } else if arg == "--explain" || arg.starts_with("--explain=") {
+            let code = match arg.split_once('=') {
+                Some((_, code)) => code,
+                None => args.next().ok_or("Expected a code after --explain, e.g. --explain H0002")?,
+            };
+            explain = Some(code.to_string());
+        } else if let Some(dir) = arg.strip_prefix("-I") {
+            let dir = match dir {
+                "" => args.next().ok_or("Expected a directory after -I")?,
+                dir => dir,
+            };
+            options.include_paths.push(PathBuf::from(dir));
+        } else if arg == "--pseudo" {
+            options.pseudo_instructions = true;
+        } else if arg == "--listing" {
+            options.listing = true;
+        } else if arg == "--allow-raw-words" {
+            options.allow_raw_words = true;
+        } else if arg == "--strict" {
+            options.lints.set(Lint::NonCanonicalComp, LintLevel::Warn);
+        } else if let Some(level) = lint_flag(arg) {
+            let name = match &arg[2..] {
+                "" => args.next().ok_or(format!("Expected a lint name after {}", arg))?,
+                name => name,
+            };
+            match level {
+                LintLevel::Deny if name != "warnings" && Lint::from_name(name).is_none() => {
+                    let define = assembler::parse_define(name).map_err(|message| {
+                        format!("{}, -D also takes a lint name or `warnings`", message)
+                    })?;
+                    options.defines.push(define);
+                }
+                _ => set_lint_level(&mut options, name, level)?,
+            }
+        } else {
+            files.push(arg.clone());
+        }
+    }
+    if files.len() > MAX_NO_OF_FILES {
+        return Err(format!(
             "Too many files, Expected Max number of files is {}, found {}",
             MAX_NO_OF_FILES,
-            args.len()
-        );
+            files.len()
+        ));
+    }
+    if files.is_empty() && explain.is_none() {
+        return Err("Expected atleast one .asm file: Found none!".to_string());
+    }
+    Ok(Args {
+        files,
+        options,
+        message_format,
+        explain,
+    })
+}
+
+fn lint_flag(arg: &str) -> Option<LintLevel> {
+    match arg.get(..2)? {
+        "-W" => Some(LintLevel::Warn),
+        "-A" => Some(LintLevel::Allow),
+        "-D" => Some(LintLevel::Deny),
+        _ => None,
     }
-    if args.len() - 1 == 0 {
-        panic!("Expected atleast one .asm file: Found none!");
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    // skip(1), because the first argument is a reference to the target program
+    let Args {
+        files,
+        options,
+        message_format,
+        explain,
+    } = match parse_args(&args[1..]) {
+        Ok(parsed) => parsed,
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    };
+    if let Some(code) = explain {
+        match explain::explanation(&code) {
+            Some(explanation) => println!("{}", explanation),
+            None => {
+                eprintln!("No explanation for {}: codes look like H0002", code);
+                process::exit(2);
+            }
+        }
+        return;
     }
-    for arg in args.iter().skip(1) {
-        let file_name = assembler::extract_file_name(&arg);
-        println!("Assembling file : {}.asm",file_name);
-        let result = assembler::assemble(&arg);
+    let human = message_format == MessageFormat::Human;
+    // colour only makes sense on a terminal, NO_COLOR turns it off everywhere
+    let color = io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let mut renderer = Renderer::new(color);
+    let (mut failed, mut total_errors, mut total_warnings) = (0, 0, 0);
+    // codes of every error printed, pointed at by `--explain` once all files are done
+    let mut error_codes: Vec<String> = Vec::new();
+    for arg in &files {
+        let file_name = assembler::extract_file_name(arg);
+        if human {
+            println!("Assembling file : {}.asm",file_name);
+        }
+        let result = assembler::assemble_with_options(arg, &options);
+        let diagnostics: Vec<Diagnostic> = match &result {
+            Ok(warnings) => warnings.iter().map(Diagnostic::from).collect(),
+            Err(errors) => errors.iter().map(Diagnostic::from).collect(),
+        };
+        for diagnostic in &diagnostics {
+            if diagnostic.severity == Severity::Error && !error_codes.contains(&diagnostic.code) {
+                error_codes.push(diagnostic.code.clone());
+            }
+            if human {
+                println!("{}", renderer.render(diagnostic));
+            } else {
+                println!("{}", diagnostic.to_json());
+            }
+        }
         match result {
-            Ok(_) => println!("Assembling successful ✅ Check output/{}.hack",file_name),
-            Err(e) => println!("Failed to Assemble {}.asm : {}",file_name,e)
+            Ok(warnings) => {
+                total_warnings += warnings.len();
+                if human && !warnings.is_empty() {
+                    println!("{}.asm : {} warning(s)", file_name, warnings.len());
+                }
+                if human {
+                    println!("Assembling successful ✅ Check output/{}.hack",file_name)
+                }
+            }
+            Err(errors) => {
+                failed += 1;
+                total_errors += errors.len();
+                if human {
+                    println!("Failed to Assemble {}.asm : {} error(s)", file_name, errors.len());
+                    if options.max_errors == Some(errors.len()) {
+                        println!("Stopped after {} error(s), there may be more (--max-errors)", errors.len());
+                    }
+                }
+            }
         }
     }
-    
+    if human && !error_codes.is_empty() {
+        error_codes.sort();
+        println!("Some errors have detailed explanations: {}.", error_codes.join(", "));
+        println!("For more information about an error, try `hack_assembler --explain {}`.", error_codes[0]);
+    }
+    if !human {
+        println!("{}", json_summary(files.len(), failed, total_errors, total_warnings));
+    }
+    if failed > 0 {
+        process::exit(1);
+    }
 }
@@ -0,0 +1,413 @@
+// the three parts of a C instruction: dest=comp;jump
+// every part is an enum with exactly the forms the Hack spec allows, so a parsed C instruction
+// is always encodable. encode() places the part's bits in the 16-bit instruction word and
+// decode() reads them back out of one:
+//
+//   1 1 1 a c1 c2 c3 c4 c5 c6 d1 d2 d3 j1 j2 j3
+//         `------ comp ------' `- dest -' `jump'
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+    error::{AssembleError, ErrorKind},
+    suggest::closest,
+};
+
+// the top three bits of every C instruction
+pub const C_INSTRUCTION_PREFIX: u16 = 0b111 << 13;
+
+const COMP_SHIFT: u16 = 6;
+const COMP_MASK: u16 = 0b111_1111 << COMP_SHIFT;
+const DEST_SHIFT: u16 = 3;
+const DEST_MASK: u16 = 0b111 << DEST_SHIFT;
+const JUMP_MASK: u16 = 0b111;
+
+fn invalid_mnemonic(kind: ErrorKind, mnemonic: &str, candidates: impl IntoIterator<Item = &'static str>) -> AssembleError {
+    AssembleError::new(kind, mnemonic).with_suggestion(closest(mnemonic, candidates))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Comp {
+    Zero,
+    One,
+    MinusOne,
+    D,
+    A,
+    NotD,
+    NotA,
+    MinusD,
+    MinusA,
+    DPlusOne,
+    APlusOne,
+    DMinusOne,
+    AMinusOne,
+    DPlusA,
+    DMinusA,
+    AMinusD,
+    DAndA,
+    DOrA,
+    M,
+    NotM,
+    MinusM,
+    MPlusOne,
+    MMinusOne,
+    DPlusM,
+    DMinusM,
+    MMinusD,
+    DAndM,
+    DOrM,
+}
+
+impl Comp {
+    pub const ALL: [Comp; 28] = [
+        Comp::Zero,
+        Comp::One,
+        Comp::MinusOne,
+        Comp::D,
+        Comp::A,
+        Comp::NotD,
+        Comp::NotA,
+        Comp::MinusD,
+        Comp::MinusA,
+        Comp::DPlusOne,
+        Comp::APlusOne,
+        Comp::DMinusOne,
+        Comp::AMinusOne,
+        Comp::DPlusA,
+        Comp::DMinusA,
+        Comp::AMinusD,
+        Comp::DAndA,
+        Comp::DOrA,
+        Comp::M,
+        Comp::NotM,
+        Comp::MinusM,
+        Comp::MPlusOne,
+        Comp::MMinusOne,
+        Comp::DPlusM,
+        Comp::DMinusM,
+        Comp::MMinusD,
+        Comp::DAndM,
+        Comp::DOrM,
+    ];
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Comp::Zero => "0",
+            Comp::One => "1",
+            Comp::MinusOne => "-1",
+            Comp::D => "D",
+            Comp::A => "A",
+            Comp::NotD => "!D",
+            Comp::NotA => "!A",
+            Comp::MinusD => "-D",
+            Comp::MinusA => "-A",
+            Comp::DPlusOne => "D+1",
+            Comp::APlusOne => "A+1",
+            Comp::DMinusOne => "D-1",
+            Comp::AMinusOne => "A-1",
+            Comp::DPlusA => "D+A",
+            Comp::DMinusA => "D-A",
+            Comp::AMinusD => "A-D",
+            Comp::DAndA => "D&A",
+            Comp::DOrA => "D|A",
+            Comp::M => "M",
+            Comp::NotM => "!M",
+            Comp::MinusM => "-M",
+            Comp::MPlusOne => "M+1",
+            Comp::MMinusOne => "M-1",
+            Comp::DPlusM => "D+M",
+            Comp::DMinusM => "D-M",
+            Comp::MMinusD => "M-D",
+            Comp::DAndM => "D&M",
+            Comp::DOrM => "D|M",
+        }
+    }
+
+    // the same computation with the operands of `+`, `&` or `|` swapped, e.g. `A+D` for `D+A`
+    // code from VM translators and other assemblers often uses these spellings
+    pub fn commuted(&self) -> Option<String> {
+        match self.mnemonic().as_bytes() {
+            &[left, operator @ (b'+' | b'&' | b'|'), right] => {
+                Some(String::from_utf8(vec![right, operator, left]).unwrap())
+            }
+            _ => None,
+        }
+    }
+
+    // a c1 c2 c3 c4 c5 c6, the a bit picks M instead of A as the ALU's second input
+    fn bits(&self) -> u16 {
+        match self {
+            Comp::Zero => 0b0101010,
+            Comp::One => 0b0111111,
+            Comp::MinusOne => 0b0111010,
+            Comp::D => 0b0001100,
+            Comp::A => 0b0110000,
+            Comp::NotD => 0b0001101,
+            Comp::NotA => 0b0110001,
+            Comp::MinusD => 0b0001111,
+            Comp::MinusA => 0b0110011,
+            Comp::DPlusOne => 0b0011111,
+            Comp::APlusOne => 0b0110111,
+            Comp::DMinusOne => 0b0001110,
+            Comp::AMinusOne => 0b0110010,
+            Comp::DPlusA => 0b0000010,
+            Comp::DMinusA => 0b0010011,
+            Comp::AMinusD => 0b0000111,
+            Comp::DAndA => 0b0000000,
+            Comp::DOrA => 0b0010101,
+            Comp::M => 0b1110000,
+            Comp::NotM => 0b1110001,
+            Comp::MinusM => 0b1110011,
+            Comp::MPlusOne => 0b1110111,
+            Comp::MMinusOne => 0b1110010,
+            Comp::DPlusM => 0b1000010,
+            Comp::DMinusM => 0b1010011,
+            Comp::MMinusD => 0b1000111,
+            Comp::DAndM => 0b1000000,
+            Comp::DOrM => 0b1010101,
+        }
+    }
+
+    pub fn encode(&self) -> u16 {
+        self.bits() << COMP_SHIFT
+    }
+
+    // None when the comp bits of the word are not one of the 28 forms
+    pub fn decode(word: u16) -> Option<Comp> {
+        let bits = (word & COMP_MASK) >> COMP_SHIFT;
+        Comp::ALL.into_iter().find(|comp| comp.bits() == bits)
+    }
+}
+
+impl FromStr for Comp {
+    type Err = AssembleError;
+
+    // accepts the canonical spelling and its commuted form
+    fn from_str(mnemonic: &str) -> Result<Comp, AssembleError> {
+        Comp::ALL
+            .into_iter()
+            .find(|comp| comp.mnemonic() == mnemonic)
+            .or_else(|| {
+                Comp::ALL
+                    .into_iter()
+                    .find(|comp| comp.commuted().is_some_and(|commuted| commuted == mnemonic))
+            })
+            .ok_or_else(|| invalid_mnemonic(ErrorKind::InvalidComp, mnemonic, Comp::ALL.iter().map(Comp::mnemonic)))
+    }
+}
+
+impl fmt::Display for Comp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+// which registers receive the result, Null stores it nowhere and is written as no `dest=` at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dest {
+    Null,
+    M,
+    D,
+    MD,
+    A,
+    AM,
+    AD,
+    AMD,
+}
+
+impl Dest {
+    // in encoding order, the index is the 3-bit value
+    pub const ALL: [Dest; 8] = [
+        Dest::Null,
+        Dest::M,
+        Dest::D,
+        Dest::MD,
+        Dest::A,
+        Dest::AM,
+        Dest::AD,
+        Dest::AMD,
+    ];
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Dest::Null => "",
+            Dest::M => "M",
+            Dest::D => "D",
+            Dest::MD => "MD",
+            Dest::A => "A",
+            Dest::AM => "AM",
+            Dest::AD => "AD",
+            Dest::AMD => "AMD",
+        }
+    }
+
+    pub fn encode(&self) -> u16 {
+        (*self as u16) << DEST_SHIFT
+    }
+
+    pub fn decode(word: u16) -> Dest {
+        Dest::ALL[usize::from((word & DEST_MASK) >> DEST_SHIFT)]
+    }
+}
+
+impl FromStr for Dest {
+    type Err = AssembleError;
+
+    fn from_str(mnemonic: &str) -> Result<Dest, AssembleError> {
+        Dest::ALL
+            .into_iter()
+            .find(|dest| dest.mnemonic() == mnemonic)
+            .ok_or_else(|| invalid_mnemonic(ErrorKind::InvalidDest, mnemonic, Dest::ALL[1..].iter().map(Dest::mnemonic)))
+    }
+}
+
+impl fmt::Display for Dest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+// when to jump to the address in A, comparing the computed value with 0
+// Null never jumps and is written as no `;jump` at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Jump {
+    Null,
+    JGT,
+    JEQ,
+    JGE,
+    JLT,
+    JNE,
+    JLE,
+    JMP,
+}
+
+impl Jump {
+    // in encoding order, the index is the 3-bit value
+    pub const ALL: [Jump; 8] = [
+        Jump::Null,
+        Jump::JGT,
+        Jump::JEQ,
+        Jump::JGE,
+        Jump::JLT,
+        Jump::JNE,
+        Jump::JLE,
+        Jump::JMP,
+    ];
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Jump::Null => "",
+            Jump::JGT => "JGT",
+            Jump::JEQ => "JEQ",
+            Jump::JGE => "JGE",
+            Jump::JLT => "JLT",
+            Jump::JNE => "JNE",
+            Jump::JLE => "JLE",
+            Jump::JMP => "JMP",
+        }
+    }
+
+    pub fn encode(&self) -> u16 {
+        *self as u16
+    }
+
+    pub fn decode(word: u16) -> Jump {
+        Jump::ALL[usize::from(word & JUMP_MASK)]
+    }
+}
+
+impl FromStr for Jump {
+    type Err = AssembleError;
+
+    fn from_str(mnemonic: &str) -> Result<Jump, AssembleError> {
+        Jump::ALL
+            .into_iter()
+            .find(|jump| jump.mnemonic() == mnemonic)
+            .ok_or_else(|| invalid_mnemonic(ErrorKind::InvalidJump, mnemonic, Jump::ALL[1..].iter().map(Jump::mnemonic)))
+    }
+}
+
+impl fmt::Display for Jump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for comp in Comp::ALL {
+            assert_eq!(comp.mnemonic().parse::<Comp>().unwrap(), comp);
+            assert_eq!(Comp::decode(C_INSTRUCTION_PREFIX | comp.encode()), Some(comp));
+        }
+        for dest in Dest::ALL {
+            assert_eq!(dest.to_string().parse::<Dest>().unwrap(), dest);
+            assert_eq!(Dest::decode(C_INSTRUCTION_PREFIX | dest.encode()), dest);
+        }
+        for jump in Jump::ALL {
+            assert_eq!(jump.to_string().parse::<Jump>().unwrap(), jump);
+            assert_eq!(Jump::decode(C_INSTRUCTION_PREFIX | jump.encode()), jump);
+        }
+    }
+
+    #[test]
+    fn test_commuted_spellings() {
+        let commuted = [
+            ("A+D", Comp::DPlusA),
+            ("M+D", Comp::DPlusM),
+            ("A&D", Comp::DAndA),
+            ("M&D", Comp::DAndM),
+            ("A|D", Comp::DOrA),
+            ("M|D", Comp::DOrM),
+            ("1+D", Comp::DPlusOne),
+            ("1+A", Comp::APlusOne),
+            ("1+M", Comp::MPlusOne),
+        ];
+        for (mnemonic, comp) in commuted {
+            assert_eq!(mnemonic.parse::<Comp>().unwrap(), comp);
+            assert_eq!(comp.commuted().as_deref(), Some(mnemonic));
+        }
+        // subtraction does not commute
+        assert!("A-D".parse::<Comp>().is_ok_and(|comp| comp == Comp::AMinusD));
+        assert!("1-D".parse::<Comp>().is_err());
+        assert_eq!(Comp::DMinusA.commuted(), None);
+        assert_eq!(Comp::NotD.commuted(), None);
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(C_INSTRUCTION_PREFIX | Dest::D.encode() | Comp::DMinusM.encode(), 0b1111010011010000);
+        assert_eq!(C_INSTRUCTION_PREFIX | Comp::Zero.encode() | Jump::JMP.encode(), 0b1110101010000111);
+        assert_eq!(C_INSTRUCTION_PREFIX | Dest::AMD.encode(), 0b1110000000111000);
+    }
+
+    #[test]
+    fn test_decode_unknown_comp() {
+        // a=1 with the c bits of 0 has no mnemonic
+        assert_eq!(Comp::decode(0b1111101010000000), None);
+    }
+
+    #[test]
+    fn test_invalid_mnemonics() {
+        // there is no A+M, the ALU cannot read A and M at once
+        assert_eq!("A+M".parse::<Comp>().unwrap_err().kind, ErrorKind::InvalidComp);
+        assert_eq!("D+X".parse::<Comp>().unwrap_err().kind, ErrorKind::InvalidComp);
+        assert_eq!("X".parse::<Dest>().unwrap_err().kind, ErrorKind::InvalidDest);
+        assert_eq!("JMPP".parse::<Jump>().unwrap_err().kind, ErrorKind::InvalidJump);
+    }
+
+    #[test]
+    fn test_suggestions() {
+        assert_eq!("JMPP".parse::<Jump>().unwrap_err().suggestion.as_deref(), Some("JMP"));
+        assert_eq!("jne".parse::<Jump>().unwrap_err().suggestion.as_deref(), Some("JNE"));
+        assert_eq!("DM".parse::<Dest>().unwrap_err().suggestion.as_deref(), Some("MD"));
+        assert_eq!("1-D".parse::<Comp>().unwrap_err().suggestion.as_deref(), Some("D-1"));
+        // a comp one char away from another is as likely a mistake in what was meant as a typo
+        assert_eq!("M+!".parse::<Comp>().unwrap_err().suggestion, None);
+        assert_eq!("A+M".parse::<Comp>().unwrap_err().suggestion, None);
+        assert_eq!("GOTO".parse::<Jump>().unwrap_err().suggestion, None);
+    }
+}
@@ -0,0 +1,86 @@
+// output backends, each one turns the machine code image into the contents of a file
+// the assembler itself only ever produces the image: one 16-bit word per ROM address
+
+use std::{fmt::Write, rc::Rc};
+
+// one instruction of the program, with the word it assembled into
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingLine {
+    // the ROM address of the word, labels have the address they name
+    pub address: usize,
+    // None for labels and directives, which take no ROM
+    pub word: Option<u16>,
+    // the instruction as it was assembled, after macro and pseudo-instruction expansion
+    pub text: String,
+    // shared with every other instruction from the same file
+    pub file: Rc<str>,
+    pub line: usize,
+    // the macro invocation or pseudo-instruction the instruction came from, as written
+    pub expanded_from: Option<String>,
+}
+
+// the text format the Nand2Tetris tools load: one word per line as 16 binary digits,
+// without a newline after the last word
+pub fn hack_text(image: &[u16]) -> String {
+    let mut text = String::with_capacity(image.len() * 17);
+    for (index, word) in image.iter().enumerate() {
+        if index > 0 {
+            text.push('\n');
+        }
+        let _ = write!(text, "{:016b}", word);
+    }
+    text
+}
+
+// a listing, one line per instruction: address, word in binary, instruction and where it was
+// written, instructions that came from an expansion also show what they were expanded from
+//
+// 00002  0000000000000111  @END                      Main.asm:5  from `goto END`
+pub fn listing_text(lines: &[ListingLine]) -> String {
+    let mut text = String::new();
+    for line in lines {
+        let _ = write!(text, "{:05}  ", line.address);
+        match line.word {
+            Some(word) => {
+                let _ = write!(text, "{:016b}", word);
+            }
+            None => text.push_str("                "),
+        }
+        let _ = write!(text, "  {:<24}  {}:{}", line.text, line.file, line.line);
+        if let Some(expanded_from) = &line.expanded_from {
+            let _ = write!(text, "  from `{}`", expanded_from);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hack_text() {
+        assert_eq!(hack_text(&[2, 0b1110110000010000]), "0000000000000010\n1110110000010000");
+        assert_eq!(hack_text(&[u16::MAX]), "1111111111111111");
+        assert_eq!(hack_text(&[]), "");
+    }
+
+    #[test]
+    fn test_listing_text() {
+        let line = |address, word, text: &str, expanded_from: Option<&str>| ListingLine {
+            address,
+            word,
+            text: text.to_string(),
+            file: "Main.asm".into(),
+            line: 3,
+            expanded_from: expanded_from.map(str::to_string),
+        };
+        let listing = listing_text(&[line(2, None, "(LOOP)", None), line(2, Some(7), "@END", Some("goto END"))]);
+        assert_eq!(
+            listing,
+            "00002                    (LOOP)                    Main.asm:3\n\
+             00002  0000000000000111  @END                      Main.asm:3  from `goto END`\n"
+        );
+    }
+}
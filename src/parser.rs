@@ -11,33 +11,129 @@
 // there are 3 types of instructions: A instruction, C instruction, Symbols
 // focus on A and C rn
 
+use std::{borrow::Cow, collections::HashMap, iter, mem, rc::Rc, str::FromStr};
+
+use crate::{
+    error::{AssembleError, ErrorKind, IncludeSite, Related},
+    expression::{self, parse_constant, Expression},
+    interner::{Interner, Name},
+    lexer::{Lexer, Token, TokenKind},
+    macros::{ExpandedLine, Macro},
+    mnemonic::{Comp, Dest, Jump},
+    pseudo,
+};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum InstructionVariant {
-    A(String),
+    A(u16),
+    // @SCREEN+32*row, an A-instruction whose value depends on symbols
+    Expression(Expression),
     C {
-        comp: String,
-        dest: String,
-        jmp: String,
+        comp: Comp,
+        dest: Dest,
+        jmp: Jump,
+        // the comp was written with its operands swapped, e.g. `A+D` for `D+A`
+        commuted: bool,
     },
-    Symbol{ symbol : String, is_variable : bool},
+    Symbol{ symbol : Name, is_variable : bool},
+    // `.equ NAME value` or `#define NAME value`, the value is evaluated in the first pass
+    Constant { name: Name, value: Expression },
+    // `.include "path"`, the assembler reads the file in its place
+    Include { path: String },
+    // `NAME arg, arg` where NAME is a macro, the assembler expands the body in its place
+    Invocation { name: Name, args: Vec<String> },
+    // `goto LOOP` and the other pseudo-instructions, the real instructions they stand for
+    Pseudo { name: Name, text: String, lines: Vec<ExpandedLine> },
+    // `.if`, `.ifdef` or `.ifndef`, the assembler decides which branch is assembled
+    If(Condition),
+}
+
+// what an `.if` block depends on
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    // `.if expression`, true unless the expression is 0
+    Expression(Expression),
+    // `.ifdef NAME`
+    Defined(Name),
+    // `.ifndef NAME`
+    NotDefined(Name),
+}
+
+impl InstructionVariant {
+    // whether the instruction takes a ROM address, labels and directives do not
+    pub fn emits_word(&self) -> bool {
+        !matches!(
+            self,
+            InstructionVariant::Symbol { is_variable: false, .. }
+                | InstructionVariant::Constant { .. }
+                | InstructionVariant::Include { .. }
+                | InstructionVariant::Invocation { .. }
+                | InstructionVariant::Pseudo { .. }
+                | InstructionVariant::If(_)
+        )
+    }
+}
+
+// for every instruction, whether the next one that takes a ROM word is a jump, so `@LOOP`
+// followed by `0;JMP` with only labels in between is a jump target, in one pass from the end
+pub(crate) fn jumps_next(instructions: &[Instruction]) -> Vec<bool> {
+    let mut jumps = vec![false; instructions.len()];
+    let mut next_jumps = false;
+    for (index, instruction) in instructions.iter().enumerate().rev() {
+        jumps[index] = next_jumps;
+        if instruction.variant.emits_word() {
+            next_jumps = matches!(&instruction.variant, InstructionVariant::C { jmp, .. } if *jmp != Jump::Null);
+        }
+    }
+    jumps
 }
+// 1-based columns of an instruction in its source line, end is exclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct Instruction {
     pub variant: InstructionVariant,
-    pub line_number: u16,
+    // where the instruction was written: file, 1-based line and columns in that line
+    // the file name is shared by every instruction of the file
+    pub file: Rc<str>,
+    pub line_number: usize,
+    pub span: Span,
+    // where the instruction lives in ROM, labels hold the address of the next instruction
+    // counted without a limit, the assembler rejects addresses past the end of ROM
+    pub rom_address: usize,
+    // shared by every instruction of the file, empty in the main file
+    pub included_from: Rc<[IncludeSite]>,
 }
 
 impl Instruction {
+    // the instruction as it was written, minus whitespace and comments
     pub fn rep(&self) -> String {
+        self.render(false)
+    }
+
+    // the instruction spelled the way the Hack spec does, `D=A+D` becomes `D=D+A`
+    pub fn canonical_rep(&self) -> String {
+        self.render(true)
+    }
+
+    fn render(&self, canonical: bool) -> String {
         match &self.variant {
             InstructionVariant::A(addr) => format!("@{}", addr),
-            InstructionVariant::C { comp, dest, jmp } => {
+            InstructionVariant::Expression(expression) => format!("@{}", expression),
+            InstructionVariant::C { comp, dest, jmp, commuted } => {
                 let mut build_string = String::new();
-                if !dest.is_empty() {
+                if *dest != Dest::Null {
                     build_string.push_str(&format!("{}=", dest));
                 }
-                build_string.push_str(&comp);
-                if !jmp.is_empty() {
+                match comp.commuted() {
+                    Some(commuted_comp) if *commuted && !canonical => build_string.push_str(&commuted_comp),
+                    _ => build_string.push_str(comp.mnemonic()),
+                }
+                if *jmp != Jump::Null {
                     build_string.push_str(&format!(";{}", jmp));
                 }
                 build_string
@@ -49,105 +145,744 @@ impl Instruction {
                     format!("({})", symbol)
                 }
             }
+            InstructionVariant::Constant { name, value } => format!(".equ {} {}", name, value),
+            InstructionVariant::Include { path } => format!(".include \"{}\"", path),
+            InstructionVariant::Invocation { name, args } if args.is_empty() => name.to_string(),
+            InstructionVariant::Invocation { name, args } => format!("{} {}", name, args.join(", ")),
+            InstructionVariant::Pseudo { text, .. } => text.clone(),
+            InstructionVariant::If(Condition::Expression(expression)) => format!(".if {}", expression),
+            InstructionVariant::If(Condition::Defined(name)) => format!(".ifdef {}", name),
+            InstructionVariant::If(Condition::NotDefined(name)) => format!(".ifndef {}", name),
         }
     }
 }
 
+// A-instructions load a 15-bit value, the top bit of the word must stay 0
+// or the CPU decodes it as a C instruction
+pub const MAX_CONSTANT: u32 = 32767;
+
+// the number of instructions the Hack ROM holds
+pub const ROM_SIZE: usize = 32768;
+
 pub struct Parser {
-    pub instructions_count: u16,
+    pub instructions_count: usize,
+    // the file being parsed and the 1-based number of the line read next
+    pub file: Rc<str>,
+    pub line_number: usize,
+    // the limit for constants is 65535 instead of 32767
+    pub allow_raw_words: bool,
+    // a line starting with `goto`, `inc` and the like is a pseudo-instruction
+    pub pseudo_instructions: bool,
+    // accept `/* ... */` comments besides `//` ones
+    pub block_comments: bool,
+    // line and column of a `/*` that has not been closed yet
+    open_comment: Option<(usize, usize)>,
+    // how the file being parsed was reached, innermost `.include` first
+    included_from: Rc<[IncludeSite]>,
+    // the macro whose body is being read, up to its `.endm`
+    recording: Option<Macro>,
+    // every macro defined so far, a line starting with one of these names invokes it
+    macros: HashMap<Name, Rc<Macro>>,
+    // the `.if` blocks the line is in, innermost last
+    conditionals: Vec<Conditional>,
+    interner: Interner,
+}
+
+// which lines of an `.if` block are assembled
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Branch {
+    // the lines up to `.else` or `.endif` are assembled
+    Taken,
+    // the lines are skipped, the ones after `.else` are assembled
+    Waiting,
+    // nothing up to `.endif` is assembled, the condition was false after `.else`,
+    // could not be evaluated or the whole block is inside a skipped one
+    Skipped,
+}
+
+// an `.if` whose `.endif` has not been read yet
+#[derive(Debug)]
+struct Conditional {
+    branch: Branch,
+    seen_else: bool,
+    // `.if`, `.ifdef` or `.ifndef` and where it was written
+    directive: &'static str,
+    line_number: usize,
+    column: usize,
+}
+
+// where the parser was in a file, put back once the file it included has been parsed
+pub struct FileState {
+    file: Rc<str>,
+    line_number: usize,
+    open_comment: Option<(usize, usize)>,
+    included_from: Rc<[IncludeSite]>,
+    conditionals: Vec<Conditional>,
 }
 
 impl Parser {
     pub fn new() -> Self {
         Self {
             instructions_count: 0,
+            file: Rc::from(""),
+            line_number: 1,
+            allow_raw_words: false,
+            pseudo_instructions: false,
+            block_comments: true,
+            open_comment: None,
+            included_from: Rc::from([]),
+            recording: None,
+            macros: HashMap::new(),
+            conditionals: Vec::new(),
+            interner: Interner::new(),
         }
     }
-    pub fn parse(&mut self, instruction: String) -> Instruction {
-        if instruction.len() < 1 {
-            panic!("invalid!!!")
+
+    pub fn for_file(file: &str) -> Self {
+        Self {
+            file: Rc::from(file),
+            ..Self::new()
         }
-        
-        let mut cleaned_line: String = instruction
-            .chars()
-            .filter(|char| !char.is_whitespace())
-            .collect();
-        let first_char = cleaned_line.chars().nth(0).unwrap();
-        let parsed: Instruction;
-        
-        if first_char == '@' {
-            // A instruction or variable symbol
-            cleaned_line = cleaned_line.chars().filter(|char| *char != '@').collect();
-            // check if the instruction is strictly an A-instruction like @10 -> checks 10
-            //  cos we could have @var -> checks var
-            if let Ok(_) = cleaned_line.parse::<u32>() {
-                // cleaned_line is a number and hence a valid A-instruction
-                parsed = Instruction {
-                    variant: InstructionVariant::A(cleaned_line),
-                    line_number: self.instructions_count,
+    }
+
+    // switches to a file pulled in by the `.include` or macro invocation at site, starting at
+    // line_number, labels and addresses carry on from the including file
+    // the returned state goes back to `leave_file` afterwards
+    pub fn enter_file(&mut self, file: &str, line_number: usize, site: IncludeSite) -> FileState {
+        let included_from = iter::once(site).chain(self.included_from.iter().cloned()).collect();
+        FileState {
+            file: mem::replace(&mut self.file, Rc::from(file)),
+            line_number: mem::replace(&mut self.line_number, line_number),
+            open_comment: self.open_comment.take(),
+            included_from: mem::replace(&mut self.included_from, included_from),
+            conditionals: mem::take(&mut self.conditionals),
+        }
+    }
+
+    // continues the including file after the line with the `.include`
+    pub fn leave_file(&mut self, state: FileState) {
+        self.file = state.file;
+        self.line_number = state.line_number;
+        self.open_comment = state.open_comment;
+        self.included_from = state.included_from;
+        self.conditionals = state.conditionals;
+    }
+
+    // reads the next line of the source file, blank and comment-only lines produce no instruction
+    // a line can be wrong in more than one place (`X=D+X;JMPP`), every problem is returned
+    pub fn parse_line(&mut self, line: &str) -> Result<Option<Instruction>, Vec<AssembleError>> {
+        let mut lexer = Lexer::new(line)
+            .block_comments(self.block_comments)
+            .inside_block_comment(self.open_comment.is_some());
+        let mut tokens: Vec<Token> = lexer.by_ref().collect();
+        if !lexer.in_block_comment() {
+            self.open_comment = None;
+        } else if let Some(opened) = tokens.last().filter(|_| self.open_comment.is_none()) {
+            // the last token on the line is the comment that stays open
+            self.open_comment = Some((self.line_number, opened.span.start));
+        }
+        tokens.retain(|token| token.kind != TokenKind::Comment);
+        let parsed = match (tokens.first(), tokens.last()) {
+            _ if self.recording.is_some() => self.record(&tokens, line).map(|()| None),
+            _ if self.skipping() => self.skip(&tokens).map(|()| None),
+            (Some(first), Some(last)) => {
+                let span = Span {
+                    start: first.span.start,
+                    end: last.span.end,
                 };
-            } else {
-                parsed = Instruction {
-                    variant: InstructionVariant::Symbol { symbol: cleaned_line, is_variable: true }, // it's a variable symbol
-                    line_number: self.instructions_count, 
+                self.parse_tokens(&tokens, line, span)
+            }
+            _ => Ok(None),
+        };
+        let parsed = parsed.map_err(|errors| {
+            errors.into_iter().map(|error| error.included_from(&self.included_from)).collect()
+        });
+        self.line_number += 1;
+        parsed
+    }
+
+    // the error for a block comment still open once the whole file has been read
+    pub fn unterminated_comment(&self) -> Option<AssembleError> {
+        self.open_comment
+            .map(|(line, column)| {
+                AssembleError::new(ErrorKind::UnterminatedComment, "/*")
+                    .at(&self.file, line, column)
+                    .included_from(&self.included_from)
+            })
+    }
+
+    // the error for a `.macro` still open once the whole file has been read, the definition is dropped
+    pub fn unterminated_macro(&mut self) -> Option<AssembleError> {
+        self.recording.take().map(|definition| {
+            AssembleError::new(ErrorKind::UnterminatedMacro, &definition.name)
+                .at(&definition.file, definition.line_number, definition.column)
+                .included_from(&self.included_from)
+        })
+    }
+
+    // the error for an `.if` still open once the whole file has been read
+    pub fn unterminated_conditional(&mut self) -> Option<AssembleError> {
+        let outermost = self.conditionals.drain(..).next()?;
+        Some(
+            AssembleError::new(ErrorKind::UnterminatedConditional, outermost.directive)
+                .at(&self.file, outermost.line_number, outermost.column)
+                .included_from(&self.included_from),
+        )
+    }
+
+    // opens the block of an `If` instruction, taken is whether its condition held,
+    // None if it could not be evaluated, then neither branch is assembled
+    pub fn begin_conditional(&mut self, taken: Option<bool>, instruction: &Instruction) {
+        let branch = match taken {
+            Some(true) => Branch::Taken,
+            Some(false) => Branch::Waiting,
+            None => Branch::Skipped,
+        };
+        let directive = match &instruction.variant {
+            InstructionVariant::If(Condition::Defined(_)) => ".ifdef",
+            InstructionVariant::If(Condition::NotDefined(_)) => ".ifndef",
+            _ => ".if",
+        };
+        self.conditionals.push(Conditional {
+            branch,
+            seen_else: false,
+            directive,
+            line_number: instruction.line_number,
+            column: instruction.span.start,
+        });
+    }
+
+    // whether the line is in a branch that is not assembled
+    fn skipping(&self) -> bool {
+        self.conditionals.last().is_some_and(|conditional| conditional.branch != Branch::Taken)
+    }
+
+    // a line in a branch that is not assembled, only the directives that open and close blocks count
+    fn skip(&mut self, tokens: &[Token]) -> Result<(), Vec<AssembleError>> {
+        match tokens.first() {
+            Some(first) if is_if_directive(first) => {
+                self.conditionals.push(Conditional {
+                    branch: Branch::Skipped,
+                    seen_else: false,
+                    directive: if_directive(first.text),
+                    line_number: self.line_number,
+                    column: first.span.start,
+                });
+                Ok(())
+            }
+            Some(first) if is_directive(first, ".else") || is_directive(first, ".endif") => {
+                self.end_branch(tokens).map_err(|error| vec![error])
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // `.else` or `.endif`
+    fn end_branch(&mut self, tokens: &[Token]) -> Result<(), AssembleError> {
+        let directive = &tokens[0];
+        if let Some(extra) = tokens.get(1) {
+            return Err(self.unexpected(extra, &format!("`{}` must be on a line of its own", directive.text)));
+        }
+        let Some(conditional) = self.conditionals.last_mut() else {
+            return Err(self.unexpected(directive, &format!("`{}` without an `.if` above it", directive.text)));
+        };
+        if directive.text == ".endif" {
+            self.conditionals.pop();
+            return Ok(());
+        }
+        if conditional.seen_else {
+            return Err(self.unexpected(directive, "this `.if` already has an `.else`"));
+        }
+        conditional.seen_else = true;
+        conditional.branch = match conditional.branch {
+            Branch::Waiting => Branch::Taken,
+            Branch::Taken | Branch::Skipped => Branch::Skipped,
+        };
+        Ok(())
+    }
+
+    // the macro a `Invocation` instruction names
+    pub fn macro_definition(&self, name: &str) -> Option<Rc<Macro>> {
+        self.macros.get(name).cloned()
+    }
+
+    // a line between `.macro` and `.endm`, kept as written until the macro is invoked
+    fn record(&mut self, tokens: &[Token], line: &str) -> Result<(), Vec<AssembleError>> {
+        let label = match tokens {
+            [first, rest @ ..] if is_directive(first, ".endm") => {
+                if let Some(definition) = self.recording.take() {
+                    // a duplicate keeps the first definition, the error was reported at its `.macro`
+                    self.macros.entry(Rc::clone(&definition.name)).or_insert(Rc::new(definition));
+                }
+                return match rest.first() {
+                    Some(extra) => Err(vec![self.unexpected(extra, "`.endm` must be on a line of its own")]),
+                    None => Ok(()),
                 };
             }
+            [first, ..] if is_directive(first, ".macro") => {
+                return Err(vec![self.unexpected(first, "macros cannot be defined inside other macros, expected `.endm`")]);
+            }
+            // labels defined in the body are local to every expansion
+            [open, name, close]
+                if (open.kind, name.kind, close.kind)
+                    == (TokenKind::LeftParen, TokenKind::Identifier, TokenKind::RightParen) =>
+            {
+                Some(self.interner.intern(name.text))
+            }
+            _ => None,
+        };
+        if let Some(definition) = &mut self.recording {
+            definition.push_line(line, label);
+        }
+        Ok(())
+    }
+
+    fn parse_tokens(&mut self, tokens: &[Token], line: &str, span: Span) -> Result<Option<Instruction>, Vec<AssembleError>> {
+        let rom_address = self.instructions_count;
+        // a macro with the name of a pseudo-instruction takes precedence
+        let is_pseudo = pseudo::is_pseudo(&tokens[0])
+            && (self.pseudo_instructions || tokens.len() > 1)
+            && !self.is_invocation(tokens);
+        if is_pseudo && !self.pseudo_instructions {
+            return Err(vec![self
+                .error(ErrorKind::InvalidInstruction, spanned(line, tokens), span.start)
+                .with_related(Related::note(&format!(
+                    "`{}` is a pseudo-instruction, pass --pseudo to use them",
+                    tokens[0].text
+                )))]);
+        }
+        // pseudo-instructions use `[`, `]`, `<` and `>`, which are not Hack assembly
+        if let Some(unknown) = tokens.iter().find(|token| token.kind == TokenKind::Unknown).filter(|_| !is_pseudo) {
+            return Err(vec![self.unexpected(unknown, &format!("`{}` is not part of Hack assembly", unknown.text))]);
+        }
+        let is_include = is_directive(&tokens[0], ".include");
+        if let Some(string) = tokens.iter().find(|token| token.kind == TokenKind::String).filter(|_| !is_include) {
+            return Err(vec![self.unexpected(string, "only `.include` takes a quoted path")]);
+        }
+        let variant = match tokens[0].kind {
+            _ if is_include => self.parse_include(tokens, line, span).map_err(|error| vec![error])?,
+            _ if is_pseudo => InstructionVariant::Pseudo {
+                name: self.interner.intern(tokens[0].text),
+                text: spanned(line, tokens).to_string(),
+                lines: pseudo::expand(tokens, line).map_err(|error| {
+                    let column = error.column;
+                    vec![error.at(&self.file, self.line_number, column)]
+                })?,
+            },
+            _ if is_directive(&tokens[0], ".macro") => {
+                return self.start_macro(tokens, span).map(|()| None).map_err(|error| vec![error]);
+            }
+            _ if is_if_directive(&tokens[0]) => self.parse_condition(tokens, span).map_err(|error| vec![error])?,
+            _ if is_directive(&tokens[0], ".else") || is_directive(&tokens[0], ".endif") => {
+                return self.end_branch(tokens).map(|()| None).map_err(|error| vec![error]);
+            }
+            _ if is_directive(&tokens[0], ".endm") => {
+                return Err(vec![self.unexpected(&tokens[0], "`.endm` without a `.macro` above it")]);
+            }
+            TokenKind::Identifier if self.is_invocation(tokens) => {
+                self.parse_invocation(tokens, line, span).map_err(|error| vec![error])?
+            }
+            _ if is_constant_directive(&tokens[0]) => self.parse_constant(tokens, line, span).map_err(|error| vec![error])?,
+            TokenKind::Directive => {
+                return Err(vec![self.unexpected(&tokens[0], "the only `#` directive is `#define NAME value`")])
+            }
+            TokenKind::At => self.parse_a_instruction(tokens, line, span).map_err(|error| vec![error])?,
+            TokenKind::LeftParen => self.parse_label(tokens, line, span).map_err(|error| vec![error])?,
+            _ => self.parse_c_instruction(tokens, line, span)?,
+        };
+        if variant.emits_word() {
             self.instructions_count += 1;
-        } else if first_char == '(' {
-            // label symbol
-            cleaned_line = cleaned_line
-                .chars()
-                .filter(|char| *char != '(' && *char != ')')
-                .collect();
-            parsed = Instruction {
-                variant: InstructionVariant::Symbol { symbol: cleaned_line, is_variable: false }, // it's not a variable , it's a label
-                line_number: self.instructions_count,
-            };
+        }
+        Ok(Some(Instruction {
+            variant,
+            file: Rc::clone(&self.file),
+            line_number: self.line_number,
+            span,
+            rom_address,
+            included_from: Rc::clone(&self.included_from),
+        }))
+    }
+
+    fn error(&self, kind: ErrorKind, text: &str, column: usize) -> AssembleError {
+        AssembleError::new(kind, text).at(&self.file, self.line_number, column)
+    }
+
+    // an UnexpectedToken error at token, `expected` says what would fit there
+    fn unexpected(&self, token: &Token, expected: &str) -> AssembleError {
+        self.error(ErrorKind::UnexpectedToken, token.text, token.span.start)
+            .with_related(Related::note(expected))
+    }
+
+    // the largest constant an A-instruction may load
+    pub fn max_constant(&self) -> u32 {
+        if self.allow_raw_words {
+            u32::from(u16::MAX)
         } else {
-            // C instruction
-            // first split
-            let splitted: Vec<&str> = cleaned_line.split("=").collect();
-            let mut dest: String = String::new();
-            let mut comp: String = String::new();
-            let mut jmp: String = String::new();
-
-            // splitted contains dest, comp and jmp
-            let jmp_statement: &str;
-
-            if splitted.len() == 2 {
-                // has a comp and JMP statement
-                dest = splitted[0].to_string();
-                jmp_statement = splitted[1];
-            }
-            // only has a JMP statement
-            else if splitted.len() == 1 {
-                jmp_statement = splitted[0];
-            } else {
-                panic!("invalid instruction count!")
-            }
-            // second split
-            let splitted_jmp_statement: Vec<&str> = jmp_statement.split(";").collect();
-            // println!("{:?}",splitted_jmp_statement);
-            if splitted_jmp_statement.len() == 2 {
-                comp = splitted_jmp_statement[0].to_string();
-                jmp = splitted_jmp_statement[1].to_string();
-            } else if splitted_jmp_statement.len() == 1 {
-                comp = splitted_jmp_statement[0].to_string();
-            }
-
-            // so i m thinking , depending on how I implement the mnemonic converter,
-            // the case where dest = "" or jmp = "", i wanted to set them to "null" but
-            // i dont think it's necessary, keeping this here in case.
-            parsed = Instruction {
-                variant: InstructionVariant::C { comp, dest, jmp },
-                line_number: self.instructions_count,
+            MAX_CONSTANT
+        }
+    }
+
+    // @constant, @symbol or @expression
+    fn parse_a_instruction(&mut self, tokens: &[Token], line: &str, span: Span) -> Result<InstructionVariant, AssembleError> {
+        let operands = &tokens[1..];
+        match operands {
+            [] => Err(self
+                .error(ErrorKind::InvalidInstruction, tokens[0].text, span.start)
+                .with_related(Related::note("expected a constant, symbol or expression after `@`"))),
+            [operand] if matches!(operand.kind, TokenKind::Number | TokenKind::Char) => {
+                // the constant has to fit in the word
+                match parse_constant(operand.text) {
+                    Ok(constant) if constant <= self.max_constant() => Ok(InstructionVariant::A(constant as u16)),
+                    Ok(_) => Err(self.error(ErrorKind::ConstantOutOfRange, operand.text, operand.span.start)),
+                    Err(kind) => Err(self.error(kind, operand.text, operand.span.start)),
+                }
+            }
+            [operand] if operand.kind == TokenKind::Identifier => Ok(InstructionVariant::Symbol {
+                symbol: self.interner.intern(operand.text),
+                is_variable: true,
+            }),
+            _ => {
+                let column = operands[0].span.start;
+                let source = spanned(line, operands);
+                let expression = expression::parse(operands, &mut self.interner).map_err(|error| {
+                    let column = error.column;
+                    error.at(&self.file, self.line_number, column)
+                })?;
+                if !expression.symbols().is_empty() {
+                    return Ok(InstructionVariant::Expression(expression));
+                }
+                // only constants, the value is known right away
+                let value = expression
+                    .evaluate(&mut |symbol| Err(AssembleError::new(ErrorKind::UnknownSymbol, symbol)))
+                    .map_err(|error| self.error(error.kind, source, column))?;
+                match u32::try_from(value) {
+                    Ok(value) if value <= self.max_constant() => Ok(InstructionVariant::A(value as u16)),
+                    _ => Err(self.error(ErrorKind::ConstantOutOfRange, source, column)),
+                }
+            }
+        }
+    }
+
+    // .equ NAME value or #define NAME value, the value is any A-instruction expression
+    fn parse_constant(&mut self, tokens: &[Token], line: &str, span: Span) -> Result<InstructionVariant, AssembleError> {
+        let directive = tokens[0].text;
+        let name = match tokens.get(1) {
+            Some(name) if name.kind == TokenKind::Identifier => name,
+            Some(other) => return Err(self.unexpected(other, "expected the name of the constant")),
+            None => {
+                return Err(self
+                    .error(ErrorKind::InvalidInstruction, spanned(line, tokens), span.start)
+                    .with_related(Related::note(&format!("expected `{} NAME value`", directive))))
+            }
+        };
+        if tokens.len() == 2 {
+            return Err(self
+                .error(ErrorKind::InvalidInstruction, spanned(line, tokens), span.start)
+                .with_related(Related::note(&format!("expected a value after `{} {}`", directive, name.text))));
+        }
+        let value = expression::parse(&tokens[2..], &mut self.interner).map_err(|error| {
+            let column = error.column;
+            error.at(&self.file, self.line_number, column)
+        })?;
+        Ok(InstructionVariant::Constant {
+            name: self.interner.intern(name.text),
+            value,
+        })
+    }
+
+    // .include "path", the path is resolved by the assembler
+    fn parse_include(&self, tokens: &[Token], line: &str, span: Span) -> Result<InstructionVariant, AssembleError> {
+        match &tokens[1..] {
+            [] => Err(self
+                .error(ErrorKind::InvalidInstruction, tokens[0].text, span.start)
+                .with_related(Related::note("expected `.include \"path\"`"))),
+            [path, ..] if path.kind != TokenKind::String => {
+                Err(self.unexpected(path, "expected the path of the file in double quotes"))
+            }
+            [path, ..] if path.text.len() < 2 || !path.text.ends_with('"') => Err(self
+                .error(ErrorKind::InvalidInstruction, spanned(line, tokens), span.start)
+                .with_related(Related::note("the path is missing its closing `\"`"))),
+            [_, extra, ..] => Err(self.unexpected(extra, "`.include` takes a single path")),
+            [path] => Ok(InstructionVariant::Include {
+                path: path.text[1..path.text.len() - 1].to_string(),
+            }),
+        }
+    }
+
+    // .if expression, .ifdef NAME or .ifndef NAME
+    fn parse_condition(&mut self, tokens: &[Token], span: Span) -> Result<InstructionVariant, AssembleError> {
+        let directive = tokens[0].text;
+        let condition = match (directive, &tokens[1..]) {
+            (_, []) => {
+                let expected = if directive == ".if" { "an expression" } else { "a symbol name" };
+                return Err(self
+                    .error(ErrorKind::InvalidInstruction, directive, span.start)
+                    .with_related(Related::note(&format!("expected {} after `{}`", expected, directive))));
+            }
+            (".if", operands) => Condition::Expression(expression::parse(operands, &mut self.interner).map_err(|error| {
+                let column = error.column;
+                error.at(&self.file, self.line_number, column)
+            })?),
+            (_, [name]) if name.kind == TokenKind::Identifier => {
+                let name = self.interner.intern(name.text);
+                if directive == ".ifdef" {
+                    Condition::Defined(name)
+                } else {
+                    Condition::NotDefined(name)
+                }
+            }
+            (_, [name]) => return Err(self.unexpected(name, "expected a symbol name")),
+            (_, [_, extra, ..]) => {
+                return Err(self.unexpected(extra, &format!("`{}` takes a single symbol name", directive)))
+            }
+        };
+        Ok(InstructionVariant::If(condition))
+    }
+
+    // .macro NAME param, param, the lines up to `.endm` are recorded as its body
+    // a definition with a bad parameter list is still recorded, so its body is not read as code
+    fn start_macro(&mut self, tokens: &[Token], span: Span) -> Result<(), AssembleError> {
+        let name = match tokens.get(1) {
+            Some(name) if name.kind == TokenKind::Identifier => name,
+            Some(other) => return Err(self.unexpected(other, "expected the name of the macro")),
+            None => {
+                return Err(self
+                    .error(ErrorKind::InvalidInstruction, tokens[0].text, span.start)
+                    .with_related(Related::note("expected `.macro NAME` followed by its parameters")))
+            }
+        };
+        let mut params: Vec<Name> = Vec::new();
+        let mut error = None;
+        for group in self.comma_separated(&tokens[2..], "expected a parameter name")? {
+            match group {
+                [param] if param.kind == TokenKind::Identifier && !params.iter().any(|name| &**name == param.text) => {
+                    params.push(self.interner.intern(param.text))
+                }
+                [param] if param.kind == TokenKind::Identifier => {
+                    error = error.or(Some(self.unexpected(param, "the macro already has a parameter with this name")))
+                }
+                [param] => error = error.or(Some(self.unexpected(param, "expected a parameter name"))),
+                [_, second, ..] => error = error.or(Some(self.unexpected(second, "parameters are separated by commas"))),
+                [] => {}
+            }
+        }
+        if let Some(first) = self.macros.get(name.text) {
+            error = Some(self.error(ErrorKind::DuplicateMacro, name.text, name.span.start).with_related(Related {
+                message: "first defined here".to_string(),
+                file: first.file.to_string(),
+                line: first.line_number,
+                column: first.column,
+                text: format!(".macro {}", first.name),
+            }));
+        }
+        let name = self.interner.intern(name.text);
+        self.recording = Some(Macro::new(name, params, Rc::clone(&self.file), self.line_number, span.start));
+        error.map_or(Ok(()), Err)
+    }
+
+    // a line starting with the name of a macro, unless it is a C instruction like `D=A`
+    fn is_invocation(&self, tokens: &[Token]) -> bool {
+        self.macros.contains_key(tokens[0].text)
+            && !tokens.get(1).is_some_and(|token| matches!(token.kind, TokenKind::Equals | TokenKind::Semicolon))
+    }
+
+    // NAME arg, arg, every argument is the source text between the commas
+    fn parse_invocation(&self, tokens: &[Token], line: &str, span: Span) -> Result<InstructionVariant, AssembleError> {
+        let definition = &self.macros[tokens[0].text];
+        let args: Vec<String> = self
+            .comma_separated(&tokens[1..], "expected an argument")?
+            .into_iter()
+            .map(|group| spanned(line, group).to_string())
+            .collect();
+        if args.len() != definition.params.len() {
+            let expected = match definition.params.len() {
+                0 => format!("`{}` takes no arguments", definition.name),
+                count => {
+                    let params: Vec<&str> = definition.params.iter().map(|param| &**param).collect();
+                    format!("`{}` takes {} argument(s): {}", definition.name, count, params.join(", "))
+                }
             };
-            self.instructions_count += 1;
+            return Err(self
+                .error(ErrorKind::MacroArguments, spanned(line, tokens), span.start)
+                .with_related(Related::note(&expected)));
+        }
+        Ok(InstructionVariant::Invocation {
+            name: Rc::clone(&definition.name),
+            args,
+        })
+    }
+
+    // splits `a, b + 1, c` at the commas, an empty item is reported at the comma before it
+    fn comma_separated<'t, 'a>(&self, tokens: &'t [Token<'a>], missing: &str) -> Result<Vec<&'t [Token<'a>]>, AssembleError> {
+        comma_separated(tokens).map_err(|comma| self.unexpected(comma, missing))
+    }
+
+    // (LABEL)
+    fn parse_label(&mut self, tokens: &[Token], line: &str, span: Span) -> Result<InstructionVariant, AssembleError> {
+        let text = spanned(line, tokens);
+        let tokens = &tokens[1..];
+        // `(LOOP` is not silently taken as `(LOOP)`
+        let Some(close) = tokens.iter().position(|token| token.kind == TokenKind::RightParen) else {
+            return Err(self
+                .error(ErrorKind::InvalidLabel, text, span.start)
+                .with_related(Related::note("expected `)` after the label name")));
+        };
+        if let Some(extra) = tokens.get(close + 1) {
+            return Err(self.unexpected(extra, "a label must be on a line of its own"));
+        }
+        match &tokens[..close] {
+            [] => Err(self
+                .error(ErrorKind::InvalidLabel, text, span.start)
+                .with_related(Related::note("expected a label name between the parentheses"))),
+            [name] if name.kind == TokenKind::Identifier => Ok(InstructionVariant::Symbol {
+                symbol: self.interner.intern(name.text),
+                is_variable: false,
+            }),
+            // whitespace never joins words, `(A B)` is two names
+            name if let Some(gap) = name.windows(2).find(|pair| pair[0].span.end < pair[1].span.start) => {
+                Err(self.unexpected(&gap[1], "a label name is a single word"))
+            }
+            // `(1abc)`, `(a-b)` or `(@@)`, the whole name is reported
+            name => Err(self.error(ErrorKind::InvalidSymbol, spanned(line, name), name[0].span.start)),
+        }
+    }
+
+    // dest=comp;jump, dest= and ;jump are optional
+    fn parse_c_instruction(&self, tokens: &[Token], line: &str, span: Span) -> Result<InstructionVariant, Vec<AssembleError>> {
+        let text = spanned(line, tokens);
+        let count = |kind| tokens.iter().filter(|token| token.kind == kind).count();
+        let position = |kind| tokens.iter().position(|token| token.kind == kind);
+        let (equals, semicolon) = (position(TokenKind::Equals), position(TokenKind::Semicolon));
+        // `D=M=A`, `0;JMP;JMP` and `0;JMP=D` cant be split into dest, comp and jump
+        let jump_before_dest = matches!((equals, semicolon), (Some(equals), Some(semicolon)) if equals > semicolon);
+        if count(TokenKind::Equals) > 1 || count(TokenKind::Semicolon) > 1 || jump_before_dest {
+            return Err(vec![self.error(ErrorKind::InvalidInstruction, text, span.start)]);
+        }
+        let comp_start = equals.map_or(0, |index| index + 1);
+        let comp_end = semicolon.unwrap_or(tokens.len());
+
+        let dest = match equals {
+            Some(index) => self.mnemonic(
+                self.single_word(
+                &tokens[..index],
+                &tokens[index],
+                "expected a destination before `=`",
+                "a destination is a single word such as `MD`",
+            )),
+            None => Ok(Dest::Null),
+        };
+        let jmp = match semicolon {
+            Some(index) => self.mnemonic(
+                self.single_word(
+                &tokens[index + 1..],
+                &tokens[index],
+                "expected a jump mnemonic after `;`",
+                "a jump is a single mnemonic such as `JGT`",
+            )),
+            None => Ok(Jump::Null),
+        };
+
+        let comp_tokens = &tokens[comp_start..comp_end];
+        if comp_tokens.is_empty() {
+            return Err(vec![self
+                .error(ErrorKind::InvalidInstruction, text, span.start)
+                .with_related(Related::note("a C instruction needs a comp, e.g. `D=D+1` or `0;JMP`"))]);
+        }
+        let is_operand = |token: &Token| matches!(token.kind, TokenKind::Identifier | TokenKind::Number);
+        // `D M` or `D 1`, whitespace must not glue two operands into one
+        if let Some(pair) = comp_tokens.windows(2).find(|pair| is_operand(&pair[0]) && is_operand(&pair[1])) {
+            return Err(vec![self.unexpected(&pair[1], "expected an operator between the operands of the comp")]);
+        }
+        let comp_text = joined(line, comp_tokens);
+        let comp = self.mnemonic(Ok((&comp_text, comp_tokens[0].span.start)));
+
+        // every misspelled part is reported, in the order they were written
+        match (dest, comp, jmp) {
+            (Ok(dest), Ok(comp), Ok(jmp)) => Ok(InstructionVariant::C {
+                comp,
+                dest,
+                jmp,
+                commuted: comp.mnemonic() != comp_text,
+            }),
+            (dest, comp, jmp) => Err([dest.err(), comp.err(), jmp.err()].into_iter().flatten().collect()),
+        }
+    }
+
+    // turns a part of a C instruction and the column it starts at into its mnemonic
+    fn mnemonic<T>(&self, word: Result<(&str, usize), AssembleError>) -> Result<T, AssembleError>
+    where
+        T: FromStr<Err = AssembleError>,
+    {
+        let (text, column) = word?;
+        text.parse()
+            .map_err(|error: AssembleError| error.at(&self.file, self.line_number, column))
+    }
+
+    // the dest or jump of a C instruction, which is always exactly one token
+    // `separator` is the `=` or `;` it belongs to, an empty part is reported there
+    fn single_word<'a>(&self, tokens: &[Token<'a>], separator: &Token, missing: &str, extra: &str) -> Result<(&'a str, usize), AssembleError> {
+        match tokens {
+            [] => Err(self.unexpected(separator, missing)),
+            [word] => Ok((word.text, word.span.start)),
+            [_, second, ..] => Err(self.unexpected(second, extra)),
+        }
+    }
+}
+
+// `.equ` or `#define` at the start of a line, anywhere else `.equ` is just a symbol
+fn is_constant_directive(token: &Token) -> bool {
+    matches!((token.kind, token.text), (TokenKind::Identifier, ".equ") | (TokenKind::Directive, "#define"))
+}
+
+// `.include`, `.macro`, `.endif` or another directive at the start of a line, anywhere else they are just symbols
+fn is_directive(token: &Token, directive: &str) -> bool {
+    token.kind == TokenKind::Identifier && token.text == directive
+}
+
+// `.if`, `.ifdef` or `.ifndef`
+fn is_if_directive(token: &Token) -> bool {
+    token.kind == TokenKind::Identifier && matches!(token.text, ".if" | ".ifdef" | ".ifndef")
+}
+
+// the directive an `.if` block was opened with, for the error if it is never closed
+fn if_directive(text: &str) -> &'static str {
+    match text {
+        ".ifdef" => ".ifdef",
+        ".ifndef" => ".ifndef",
+        _ => ".if",
+    }
+}
+
+// splits `a, b + 1, c` at the commas, no tokens at all is an empty list
+// an empty item (`a,,b` or `a,`) is an error, with the comma before it
+pub(crate) fn comma_separated<'t, 'a>(tokens: &'t [Token<'a>]) -> Result<Vec<&'t [Token<'a>]>, &'t Token<'a>> {
+    if tokens.is_empty() {
+        return Ok(Vec::new());
+    }
+    let groups: Vec<&[Token]> = tokens.split(|token| token.kind == TokenKind::Comma).collect();
+    match groups.iter().position(|group| group.is_empty()) {
+        Some(index) => {
+            let mut commas = tokens.iter().filter(|token| token.kind == TokenKind::Comma);
+            Err(commas.nth(index.saturating_sub(1)).unwrap_or(&tokens[0]))
         }
-        return parsed;
+        None => Ok(groups),
     }
-    pub fn clear(&mut self){
-        self.instructions_count = 0;
+}
+
+// the source from the first token to the last, borrowed from the line they were lexed from
+pub(crate) fn spanned<'a>(line: &'a str, tokens: &[Token]) -> &'a str {
+    let last = tokens[tokens.len() - 1];
+    &line[tokens[0].offset..last.offset + last.text.len()]
+}
+
+// the tokens without the whitespace and comments between them, `D + M` is `D+M`
+// borrowed from the line unless something had to be left out
+fn joined<'a>(line: &'a str, tokens: &[Token<'a>]) -> Cow<'a, str> {
+    let source = spanned(line, tokens);
+    if source.len() == tokens.iter().map(|token| token.text.len()).sum::<usize>() {
+        Cow::Borrowed(source)
+    } else {
+        Cow::Owned(tokens.iter().map(|token| token.text).collect())
     }
 }
 
@@ -179,48 +914,442 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
-    fn parser_test_invalid_instruction() {
+    fn parser_test_blank_line() {
+        let mut parser = parser_init();
+        assert!(parser.parse_line("").unwrap().is_none());
+        assert!(parser.parse_line("   // only a comment").unwrap().is_none());
+        assert_eq!(parser.line_number, 3);
+    }
+
+    #[test]
+    fn parser_test_constant_range() {
+        let mut parser = parser_init();
+        assert!(parser.parse_line("@32767").is_ok());
+        for constant in ["@32768", "@40000", "@70000", "@99999999999"] {
+            let error = parser.parse_line(constant).unwrap_err().remove(0);
+            assert_eq!(error.kind, ErrorKind::ConstantOutOfRange);
+            assert_eq!(error.text, &constant[1..]);
+            assert_eq!(error.column, 2);
+        }
+    }
+
+    #[test]
+    fn parser_test_literals() {
         let mut parser = parser_init();
-        parser.parse("".to_string());
+        let literals = [
+            ("@0x4000", 16384),
+            ("@0X7fff", 32767),
+            ("@0b1010", 10),
+            ("@0o17", 15),
+            ("@'A'", 65),
+            ("@' '", 32),
+            ("@007", 7),
+        ];
+        for (literal, value) in literals {
+            let parsed = parser.parse_line(literal).unwrap().unwrap();
+            assert_eq!(parsed.variant, InstructionVariant::A(value), "{}", literal);
+        }
+        for literal in ["@0x", "@0xZZ", "@0b102", "@0o8", "@'é'"] {
+            let error = parser.parse_line(literal).unwrap_err().remove(0);
+            assert_eq!(error.kind, ErrorKind::InvalidConstant, "{}", literal);
+            assert_eq!((error.text.as_str(), error.column), (&literal[1..], 2));
+        }
+        for literal in ["@0x8000", "@0b1000000000000000", "@0xFFFFFFFFF"] {
+            assert_eq!(parser.parse_line(literal).unwrap_err()[0].kind, ErrorKind::ConstantOutOfRange);
+        }
+    }
+
+    #[test]
+    fn parser_test_symbols_are_interned() {
+        let mut parser = parser_init();
+        let names: Vec<Name> = ["(LOOP)", "@LOOP", "@LOOP+1"]
+            .into_iter()
+            .map(|line| match parser.parse_line(line).unwrap().unwrap().variant {
+                InstructionVariant::Symbol { symbol, .. } => symbol,
+                InstructionVariant::Expression(expression) => expression.symbols()[0].into(),
+                variant => panic!("{:?}", variant),
+            })
+            .collect();
+        assert!(Rc::ptr_eq(&names[0], &names[1]));
+        assert_eq!(&*names[2], "LOOP");
+    }
+
+    // cargo test --release parser_bench_throughput -- --ignored --nocapture
+    #[test]
+    #[ignore = "benchmark"]
+    fn parser_bench_throughput() {
+        // the kind of code a compiler emits: comments, labels, variables, expressions and C instructions
+        let block = "// push constant\n@SP\nAM=M+1\nA=A-1\nM=D\n(LOOP.1)\n@counter\nD = M  // decrement\n@SCREEN+32*4\nD;JGT\n";
+        let lines_per_block = block.lines().count();
+        let source = block.repeat(1_000_000 / lines_per_block);
+        let start = std::time::Instant::now();
+        let mut parser = parser_init();
+        let mut instructions = 0;
+        for line in source.lines() {
+            if parser.parse_line(line).unwrap().is_some() {
+                instructions += 1;
+            }
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        // every line but the comment
+        assert_eq!(instructions, 1_000_000 / lines_per_block * (lines_per_block - 1));
+        println!(
+            "{} lines, {:.1} MB in {:.3}s: {:.0} lines/s, {:.1} MB/s",
+            source.lines().count(),
+            source.len() as f64 / 1e6,
+            elapsed,
+            source.lines().count() as f64 / elapsed,
+            source.len() as f64 / 1e6 / elapsed,
+        );
+    }
+
+    #[test]
+    fn parser_test_constants() {
+        let mut parser = parser_init();
+        let parsed = parser.parse_line(".equ ROWS 256").unwrap().unwrap();
+        assert_eq!(
+            parsed.variant,
+            InstructionVariant::Constant { name: "ROWS".into(), value: Expression::Constant(256) }
+        );
+        let parsed = parser.parse_line("#define  WIDTH ROWS / 8 // bytes").unwrap().unwrap();
+        assert_eq!(parsed.rep(), ".equ WIDTH ROWS/8");
+        assert_eq!(parser.instructions_count, 0);
+        // `.equ` is an ordinary symbol anywhere but the start of a line
+        assert!(parser.parse_line("@.equ").is_ok());
+        let error = |line: &str| parser_init().parse_line(line).unwrap_err().remove(0);
+        assert_eq!(error(".equ").kind, ErrorKind::InvalidInstruction);
+        assert_eq!(error(".equ ROWS").related.unwrap().message, "expected a value after `.equ ROWS`");
+        assert_eq!((error(".equ 1 2").kind, error(".equ 1 2").column), (ErrorKind::UnexpectedToken, 6));
+        assert_eq!(error("#define ROWS 2 3").kind, ErrorKind::UnexpectedToken);
+        assert_eq!(error("#include x").kind, ErrorKind::UnexpectedToken);
+    }
+
+    #[test]
+    fn parser_test_includes() {
+        let mut parser = Parser::for_file("Main.asm");
+        let parsed = parser.parse_line(".include \"lib/math.asm\" // helpers").unwrap().unwrap();
+        assert_eq!(parsed.variant, InstructionVariant::Include { path: "lib/math.asm".to_string() });
+        assert_eq!(parsed.rep(), ".include \"lib/math.asm\"");
+        // the included file starts at line 1, errors in it remember the include
+        let site = IncludeSite { file: "Main.asm".to_string(), line: 1, column: 1, macro_name: None };
+        let state = parser.enter_file("lib/math.asm", 1, site.clone());
+        parser.parse_line("@2").unwrap();
+        let error = parser.parse_line("D=X").unwrap_err().remove(0);
+        assert_eq!((error.file.as_str(), error.line), ("lib/math.asm", 2));
+        assert_eq!(*error.included_from, [site]);
+        parser.leave_file(state);
+        let parsed = parser.parse_line("D=A").unwrap().unwrap();
+        assert_eq!((&*parsed.file, parsed.line_number, parsed.rom_address), ("Main.asm", 2, 1));
+        assert!(parsed.included_from.is_empty());
+        let error = |line: &str| parser_init().parse_line(line).unwrap_err().remove(0);
+        assert_eq!(error(".include").kind, ErrorKind::InvalidInstruction);
+        assert_eq!(error(".include math.asm").kind, ErrorKind::UnexpectedToken);
+        assert_eq!(error(".include \"math.asm").related.unwrap().message, "the path is missing its closing `\"`");
+        assert_eq!(error(".include \"a\" \"b\"").column, 14);
+        assert_eq!(error("@\"x\"").kind, ErrorKind::UnexpectedToken);
+    }
+
+    #[test]
+    fn parser_test_macros() {
+        let mut parser = Parser::for_file("Main.asm");
+        // the body is recorded as written, not parsed
+        for line in [".macro COPY src, dst", "(LOOP)", "  @src // from", "  D=Q", ".endm"] {
+            assert!(parser.parse_line(line).unwrap().is_none());
+        }
+        assert_eq!(parser.instructions_count, 0);
+        let parsed = parser.parse_line("COPY SCREEN + 1, x").unwrap().unwrap();
+        let args = vec!["SCREEN + 1".to_string(), "x".to_string()];
+        assert_eq!(parsed.variant, InstructionVariant::Invocation { name: "COPY".into(), args });
+        assert_eq!(parsed.rep(), "COPY SCREEN + 1, x");
+        let definition = parser.macro_definition("COPY").unwrap();
+        assert_eq!((definition.line_number, definition.params.len()), (1, 2));
+        assert_eq!(definition.expand(&parsed_args(&parsed), 1)[0].text, "(LOOP$COPY.1)");
+        let error = parser.parse_line("COPY x").unwrap_err().remove(0);
+        assert_eq!(error.kind, ErrorKind::MacroArguments);
+        assert_eq!(error.related.unwrap().message, "`COPY` takes 2 argument(s): src, dst");
+        assert_eq!(parser.parse_line("COPY x,,y").unwrap_err()[0].column, 7);
+        let error = parser.parse_line(".macro COPY").unwrap_err().remove(0);
+        assert_eq!((error.kind, error.related.unwrap().line), (ErrorKind::DuplicateMacro, 1));
+        assert_eq!(parser.parse_line(".macro X").unwrap_err()[0].kind, ErrorKind::UnexpectedToken);
+        assert!(parser.parse_line(".endm").unwrap().is_none());
+        assert_eq!(parser.macro_definition("COPY").unwrap().line_number, 1);
+        // a macro named like a register still leaves C instructions alone
+        parser.parse_line(".macro D").unwrap();
+        parser.parse_line(".endm").unwrap();
+        assert!(matches!(parser.parse_line("D=A").unwrap().unwrap().variant, InstructionVariant::C { .. }));
+        let error = |line: &str| parser_init().parse_line(line).unwrap_err().remove(0);
+        assert_eq!(error(".endm").kind, ErrorKind::UnexpectedToken);
+        assert_eq!(error(".macro").kind, ErrorKind::InvalidInstruction);
+        assert_eq!(error(".macro M a b").column, 12);
+        assert_eq!(error(".macro M a, a").column, 13);
+        let mut parser = parser_init();
+        parser.parse_line(".macro PUSH").unwrap();
+        let error = parser.unterminated_macro().unwrap();
+        assert_eq!((error.kind, error.line, error.column), (ErrorKind::UnterminatedMacro, 1, 1));
+        assert!(parser.parse_line("@PUSH").is_ok());
+    }
+
+    fn parsed_args(parsed: &Instruction) -> Vec<String> {
+        match &parsed.variant {
+            InstructionVariant::Invocation { args, .. } => args.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parser_test_pseudo_instructions() {
+        let mut parser = parser_init();
+        let error = parser.parse_line("goto LOOP").unwrap_err().remove(0);
+        assert_eq!(error.related.unwrap().message, "`goto` is a pseudo-instruction, pass --pseudo to use them");
+        parser.pseudo_instructions = true;
+        let parsed = parser.parse_line("  mov RAM[x],  RAM[y] // copy").unwrap().unwrap();
+        assert_eq!(parsed.rep(), "mov RAM[x],  RAM[y]");
+        let InstructionVariant::Pseudo { name, lines, .. } = parsed.variant else {
+            panic!("expected a pseudo-instruction");
+        };
+        assert_eq!((&*name, lines.len()), ("mov", 4));
+        assert_eq!(parser.instructions_count, 0);
+        let error = parser.parse_line("if D>1 goto END").unwrap_err().remove(0);
+        assert_eq!((error.kind, error.line, error.column), (ErrorKind::InvalidInstruction, 3, 1));
+        // the words are still ordinary symbols on their own and after `@`
+        assert!(parser.parse_line("@goto").is_ok());
+        assert!(parser.parse_line("(inc)").is_ok());
+        assert_eq!(parser.parse_line("@x[1]").unwrap_err()[0].kind, ErrorKind::UnexpectedToken);
+    }
+
+    #[test]
+    fn parser_test_conditionals() {
+        let mut parser = parser_init();
+        let parsed = parser.parse_line(".ifdef DEBUG").unwrap().unwrap();
+        assert_eq!(parsed.rep(), ".ifdef DEBUG");
+        assert!(!parsed.variant.emits_word());
+        parser.begin_conditional(Some(false), &parsed);
+        // a skipped branch is not parsed, only the directives that open and close blocks count
+        assert!(parser.parse_line("  D=X").unwrap().is_none());
+        assert!(parser.parse_line("  .if 1/0").unwrap().is_none());
+        assert!(parser.parse_line("  .else").unwrap().is_none());
+        assert!(parser.parse_line("  .endif").unwrap().is_none());
+        assert!(parser.parse_line(".else").unwrap().is_none());
+        let parsed = parser.parse_line("@TRACE").unwrap().unwrap();
+        assert_eq!((parsed.rom_address, parsed.line_number), (0, 7));
+        let error = parser.parse_line(".else").unwrap_err().remove(0);
+        assert_eq!((error.kind, error.line), (ErrorKind::UnexpectedToken, 8));
+        assert!(parser.parse_line(".endif").unwrap().is_none());
+        let error = parser.parse_line(".endif").unwrap_err().remove(0);
+        assert_eq!(error.related.unwrap().message, "`.endif` without an `.if` above it");
+        assert_eq!(parser.parse_line(".if").unwrap_err()[0].kind, ErrorKind::InvalidInstruction);
+        assert_eq!(parser.parse_line(".ifndef A B").unwrap_err()[0].kind, ErrorKind::UnexpectedToken);
+        let parsed = parser.parse_line("  .if LEVEL > 1").unwrap_err().remove(0);
+        assert_eq!((parsed.kind, parsed.column), (ErrorKind::UnexpectedToken, 13));
+        let parsed = parser.parse_line("  .if LEVEL - 1").unwrap().unwrap();
+        assert_eq!(parsed.rep(), ".if LEVEL-1");
+        parser.begin_conditional(None, &parsed);
+        // neither branch of a condition that could not be evaluated is assembled
+        parser.parse_line(".else").unwrap();
+        assert!(parser.parse_line("@1").unwrap().is_none());
+        let error = parser.unterminated_conditional().unwrap();
+        assert_eq!((error.kind, error.line, error.column), (ErrorKind::UnterminatedConditional, 14, 3));
+        assert!(parser.unterminated_conditional().is_none());
+    }
+
+    #[test]
+    fn parser_test_block_comments() {
+        let mut parser = Parser::for_file("Comments.asm");
+        assert!(parser.parse_line("/* multiply").unwrap().is_none());
+        assert!(parser.parse_line("   @R0 D=M").unwrap().is_none());
+        assert!(parser.unterminated_comment().is_some());
+        let parsed = parser.parse_line("*/ D=M /* R0 */ ;JGT // jump").unwrap().unwrap();
+        assert_eq!(parsed.rep(), "D=M;JGT");
+        assert_eq!((parsed.line_number, parsed.span), (3, Span { start: 4, end: 21 }));
+        assert!(parser.unterminated_comment().is_none());
+        parser.parse_line("@2 /* first").unwrap();
+        parser.parse_line("/* not nested").unwrap();
+        let error = parser.unterminated_comment().unwrap();
+        assert_eq!((error.kind, error.line, error.column), (ErrorKind::UnterminatedComment, 4, 4));
+    }
+
+    #[test]
+    fn parser_test_expressions() {
+        let mut parser = parser_init();
+        let parsed = parser.parse_line("@SCREEN + 32*row").unwrap().unwrap();
+        assert!(matches!(parsed.variant, InstructionVariant::Expression(_)));
+        assert_eq!(parsed.rep(), "@SCREEN+32*row");
+        assert_eq!(parser.instructions_count, 1);
+        // constant expressions are folded while parsing
+        let parsed = parser.parse_line("@(0x6000-0x4000)/2").unwrap().unwrap();
+        assert_eq!(parsed.variant, InstructionVariant::A(4096));
+        let error = parser.parse_line("@ 2 - 3").unwrap_err().remove(0);
+        assert_eq!((error.kind, error.text.as_str(), error.column), (ErrorKind::ConstantOutOfRange, "2 - 3", 3));
+        assert_eq!(parser.parse_line("@1/0").unwrap_err()[0].kind, ErrorKind::DivisionByZero);
+        let error = parser.parse_line("@(END-1").unwrap_err().remove(0);
+        assert_eq!((error.kind, error.line, error.column), (ErrorKind::UnexpectedToken, 5, 2));
+    }
+
+    #[test]
+    fn parser_test_raw_words() {
+        let mut parser = parser_init();
+        parser.allow_raw_words = true;
+        let parsed = parser.parse_line("@40000").unwrap().unwrap();
+        assert_eq!(parsed.variant, InstructionVariant::A(40000));
+        assert!(parser.parse_line("@65535").is_ok());
+        assert_eq!(parser.parse_line("@65536").unwrap_err()[0].kind, ErrorKind::ConstantOutOfRange);
+    }
+
+    #[test]
+    fn parser_test_malformed_label() {
+        let mut parser = parser_init();
+        assert_eq!(parser.parse_line("()").unwrap_err()[0].kind, ErrorKind::InvalidLabel);
+        assert_eq!(parser.parse_line("(LOOP").unwrap_err()[0].kind, ErrorKind::InvalidLabel);
+        assert_eq!(parser.parse_line("(LOOP D=M").unwrap_err()[0].kind, ErrorKind::InvalidLabel);
+        assert_eq!(parser.parse_line("(LOOP))").unwrap_err()[0].kind, ErrorKind::UnexpectedToken);
+    }
+
+    #[test]
+    fn parser_test_symbol_names() {
+        let mut parser = parser_init();
+        for valid in ["@sys.init$ret:1", "@_tmp", "(ponggame.0)", "($)"] {
+            assert!(parser.parse_line(valid).is_ok(), "{}", valid);
+        }
+        let invalid = [
+            ("@1abc", "1abc", 2),
+            ("(1abc)", "1abc", 2),
+            ("(a-b)", "a-b", 2),
+            ("( @@ )", "@@", 3),
+        ];
+        for (line, name, column) in invalid {
+            let error = parser.parse_line(line).unwrap_err().remove(0);
+            assert_eq!((error.kind, error.text.as_str(), error.column), (ErrorKind::InvalidSymbol, name, column), "{}", line);
+        }
+        // `-` and `+` are operators, not part of a name
+        let parsed = parser.parse_line("@a-b").unwrap().unwrap();
+        assert!(matches!(parsed.variant, InstructionVariant::Expression(_)));
+        assert_eq!(parser.parse_line("@x+").unwrap_err()[0].kind, ErrorKind::UnexpectedToken);
+        assert_eq!(parser.parse_line("@café").unwrap_err()[0].kind, ErrorKind::UnexpectedToken);
+    }
+
+    #[test]
+    fn parser_test_too_many_separators() {
+        let mut parser = parser_init();
+        let error = parser.parse_line("D=M=A").unwrap_err().remove(0);
+        assert_eq!(error.kind, ErrorKind::InvalidInstruction);
+        assert_eq!(error.text, "D=M=A");
+        assert!(parser.parse_line("0;JMP;JMP").is_err());
+    }
+
+    // kind, text and column of the error for a line
+    fn parse_error(line: &str) -> (ErrorKind, String, usize) {
+        let error = parser_init().parse_line(line).unwrap_err().remove(0);
+        (error.kind, error.text, error.column)
+    }
+
+    #[test]
+    fn parser_test_whitespace_between_tokens() {
+        let mut parser = parser_init();
+        let parsed = parser.parse_line("  MD = M + 1 ; JGT").unwrap().unwrap();
+        assert_eq!(parsed.rep(), "MD=M+1;JGT");
+        assert_eq!(parsed.span, Span { start: 3, end: 19 });
+        let parsed = parser.parse_line("@ R0 // comment").unwrap().unwrap();
+        assert_eq!(parsed.rep(), "@R0");
+    }
+
+    #[test]
+    fn parser_test_whitespace_does_not_join_words() {
+        assert_eq!(parse_error("D M = A"), (ErrorKind::UnexpectedToken, "M".to_string(), 3));
+        assert_eq!(parse_error("@ 1 0"), (ErrorKind::UnexpectedToken, "0".to_string(), 5));
+        assert_eq!(parse_error("D=D M"), (ErrorKind::UnexpectedToken, "M".to_string(), 5));
+        assert_eq!(parse_error("0;J MP"), (ErrorKind::UnexpectedToken, "MP".to_string(), 5));
+    }
+
+    #[test]
+    fn parser_test_unexpected_tokens() {
+        assert_eq!(parse_error("(LOOP) D=M"), (ErrorKind::UnexpectedToken, "D".to_string(), 8));
+        assert_eq!(parse_error("(A B)"), (ErrorKind::UnexpectedToken, "B".to_string(), 4));
+        assert_eq!(parse_error("D=M#1"), (ErrorKind::UnexpectedToken, "#".to_string(), 4));
+        assert_eq!(parse_error("@=").0, ErrorKind::UnexpectedToken);
+        assert_eq!(parse_error("=M"), (ErrorKind::UnexpectedToken, "=".to_string(), 1));
+        assert_eq!(parse_error("0;"), (ErrorKind::UnexpectedToken, ";".to_string(), 2));
+        assert_eq!(parse_error("@").0, ErrorKind::InvalidInstruction);
+        assert_eq!(parse_error("D=;JMP").0, ErrorKind::InvalidInstruction);
+        assert_eq!(parse_error("0;JMP=D").0, ErrorKind::InvalidInstruction);
+        let error = parser_init().parse_line("D M=A").unwrap_err().remove(0);
+        assert_eq!(error.related.unwrap().message, "a destination is a single word such as `MD`");
+    }
+
+    #[test]
+    fn parser_test_invalid_mnemonics() {
+        let errors = parser_init().parse_line("X=A+M;JMPP").unwrap_err();
+        let found: Vec<(ErrorKind, &str, usize)> = errors
+            .iter()
+            .map(|error| (error.kind.clone(), error.text.as_str(), error.column))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (ErrorKind::InvalidDest, "X", 1),
+                (ErrorKind::InvalidComp, "A+M", 3),
+                (ErrorKind::InvalidJump, "JMPP", 7),
+            ]
+        );
+        assert_eq!(errors[2].suggestion.as_deref(), Some("JMP"));
     }
 
     #[test]
     fn parser_test_c_instruction() {
         let mut parser = parser_init();
-        let parsed = parser.parse(sample_c_instruction());
+        let parsed = parser.parse_line(&sample_c_instruction()).unwrap().unwrap();
         assert_eq!(parser.instructions_count, 1);
-        assert_eq!(parsed.line_number, 0);
+        assert_eq!(parsed.line_number, 1);
+        assert_eq!(parsed.rom_address, 0);
         assert_eq!(
             parsed.variant,
             InstructionVariant::C {
-                comp: "D-M".to_string(),
-                dest: "D".to_string(),
-                jmp: String::new()
+                comp: Comp::DMinusM,
+                dest: Dest::D,
+                jmp: Jump::Null,
+                commuted: false,
             }
         );
         assert_eq!(parsed.rep(), sample_c_instruction());
     }
 
+    #[test]
+    fn parser_test_commuted_comp() {
+        let mut parser = parser_init();
+        let parsed = parser.parse_line("AM=M+D;JNE").unwrap().unwrap();
+        assert_eq!(
+            parsed.variant,
+            InstructionVariant::C {
+                comp: Comp::DPlusM,
+                dest: Dest::AM,
+                jmp: Jump::JNE,
+                commuted: true,
+            }
+        );
+        assert_eq!(parsed.rep(), "AM=M+D;JNE");
+        assert_eq!(parsed.canonical_rep(), "AM=D+M;JNE");
+    }
+
     #[test]
     fn parser_test_a_instruction() {
         let mut parser = parser_init();
-        let parsed = parser.parse(sample_a_instruction());
+        let parsed = parser.parse_line(&sample_a_instruction()).unwrap().unwrap();
         assert_eq!(parser.instructions_count, 1);
-        assert_eq!(parsed.line_number, 0);
-        assert_eq!(parsed.variant, InstructionVariant::A("10".to_string()));
+        assert_eq!(parsed.line_number, 1);
+        assert_eq!(parsed.rom_address, 0);
+        assert_eq!(parsed.variant, InstructionVariant::A(10));
         assert_eq!(parsed.rep(), sample_a_instruction());
     }
 
     #[test]
     fn parser_test_variable_symbol() {
         let mut parser = parser_init();
-        let parsed = parser.parse(sample_symbol_variable());
+        let parsed = parser.parse_line(&sample_symbol_variable()).unwrap().unwrap();
         assert_eq!(parser.instructions_count, 1);
-        assert_eq!(parsed.line_number, 0);
+        assert_eq!(parsed.line_number, 1);
+        assert_eq!(parsed.rom_address, 0);
         assert_eq!(
             parsed.variant,
-            InstructionVariant::Symbol { symbol: "var".to_string(), is_variable: true }
+            InstructionVariant::Symbol { symbol: "var".into(), is_variable: true }
         );
         assert_eq!(parsed.rep(), sample_symbol_variable());
     }
@@ -228,13 +1357,49 @@ mod tests {
     #[test]
     fn parser_test_symbol() {
         let mut parser = parser_init();
-        let parsed = parser.parse(sample_symbol());
+        let parsed = parser.parse_line(&sample_symbol()).unwrap().unwrap();
         assert_eq!(parser.instructions_count, 0);
         assert_eq!(parsed.line_number, 1);
+        assert_eq!(parsed.rom_address, 0);
         assert_eq!(
             parsed.variant,
-            InstructionVariant::Symbol { symbol: "ITSR0".to_string(), is_variable: false }
+            InstructionVariant::Symbol { symbol: "ITSR0".into(), is_variable: false }
         );
         assert_eq!(parsed.rep(), sample_symbol());
     }
+
+    #[test]
+    fn parser_test_source_locations() {
+        let mut parser = Parser::for_file("Max.asm");
+        let lines = ["// comment", "", "(LOOP)", "   @R0   // load", "\tD = M"];
+        let parsed: Vec<Instruction> = lines
+            .iter()
+            .filter_map(|line| parser.parse_line(line).unwrap())
+            .collect();
+        assert_eq!(parser.line_number, 6);
+        assert_eq!(parsed.len(), 3);
+
+        // the label sits on line 3 and points at the instruction after it
+        assert_eq!(&*parsed[0].file, "Max.asm");
+        assert_eq!(parsed[0].line_number, 3);
+        assert_eq!(parsed[0].span, Span { start: 1, end: 7 });
+        assert_eq!(parsed[0].rom_address, 0);
+
+        assert_eq!(parsed[1].line_number, 4);
+        assert_eq!(parsed[1].span, Span { start: 4, end: 7 });
+        assert_eq!(parsed[1].rom_address, 0);
+
+        assert_eq!(parsed[2].line_number, 5);
+        assert_eq!(parsed[2].span, Span { start: 2, end: 7 });
+        assert_eq!(parsed[2].rom_address, 1);
+    }
+
+    #[test]
+    fn parser_test_error_location() {
+        let mut parser = Parser::for_file("Max.asm");
+        parser.parse_line("@R0").unwrap();
+        let error = parser.parse_line("  (LOOP").unwrap_err().remove(0);
+        assert_eq!(error.kind, ErrorKind::InvalidLabel);
+        assert_eq!((error.file.as_str(), error.line, error.column), ("Max.asm", 2, 3));
+    }
 }
@@ -0,0 +1,161 @@
+// built-in pseudo-instructions for common Hack idioms, turned on with --pseudo
+// every pseudo-instruction is one line that stands for a few real instructions, the assembler
+// parses those in its place, so labels after it get the addresses the expansion really takes
+//
+//   goto LABEL            @LABEL, 0;JMP
+//   if D>0 goto LABEL     @LABEL, D;JGT      (also >=, <, <=, == and !=)
+//   mov RAM[x], RAM[y]    @y, D=M, @x, M=D   (either side can also be D)
+//   inc x / dec x         @x, M=M+1 / M=M-1  (inc D is D=D+1)
+//   clr x                 @x, M=0            (clr D is D=0)
+//   ld D, const           @const, D=A        (ld A, const is @const)
+
+use crate::{
+    error::{AssembleError, ErrorKind, Related},
+    lexer::{Token, TokenKind},
+    macros::ExpandedLine,
+    parser::{comma_separated, spanned},
+};
+
+pub const MNEMONICS: [&str; 7] = ["goto", "if", "mov", "inc", "dec", "clr", "ld"];
+
+// `D>0` and the other conditions `if` understands, with the jump each one becomes
+const CONDITIONS: [(&str, &str); 6] = [
+    ("D>0", "JGT"),
+    ("D>=0", "JGE"),
+    ("D<0", "JLT"),
+    ("D<=0", "JLE"),
+    ("D==0", "JEQ"),
+    ("D!=0", "JNE"),
+];
+
+// whether a line starting with token is a pseudo-instruction
+pub fn is_pseudo(token: &Token) -> bool {
+    token.kind == TokenKind::Identifier && MNEMONICS.contains(&token.text)
+}
+
+// what a pseudo-instruction operand refers to
+enum Operand<'t, 'a> {
+    D,
+    // `x` or `RAM[x]`, the tokens of the address
+    Memory(&'t [Token<'a>]),
+}
+
+fn operand<'t, 'a>(tokens: &'t [Token<'a>]) -> Option<Operand<'t, 'a>> {
+    match tokens {
+        [] => None,
+        [register] if register.kind == TokenKind::Identifier && register.text == "D" => Some(Operand::D),
+        [ram, open, address @ .., close] if ram.text == "RAM" && open.text == "[" && close.text == "]" => {
+            (!address.is_empty()).then_some(Operand::Memory(address))
+        }
+        address => Some(Operand::Memory(address)),
+    }
+}
+
+// the real instructions a pseudo-instruction stands for, one line each
+// errors only carry a column, the parser fills in the file and line
+pub fn expand(tokens: &[Token], line: &str) -> Result<Vec<ExpandedLine>, AssembleError> {
+    let mnemonic = tokens[0].text;
+    let column = tokens[0].span.start;
+    let operands = &tokens[1..];
+    let fixed = |text: &str| ExpandedLine::new().push(text, column);
+    // `@address`, with the address copied from the line so errors in it point at the right column
+    let at = |address: &[Token]| ExpandedLine::new().push("@", column).push_source(spanned(line, address), address[0].span.start);
+    let expanded = match mnemonic {
+        "goto" if !operands.is_empty() => Some(vec![at(operands), fixed("0;JMP")]),
+        "if" => {
+            let goto = operands.iter().position(|token| token.text == "goto");
+            goto.and_then(|goto| {
+                let (condition, target) = (&operands[..goto], &operands[goto + 1..]);
+                let condition: String = condition.iter().map(|token| token.text).collect();
+                let (_, jump) = CONDITIONS.iter().find(|(written, _)| *written == condition)?;
+                (!target.is_empty()).then(|| vec![at(target), fixed(&format!("D;{}", jump))])
+            })
+        }
+        "mov" => match comma_separated(operands).ok().as_deref() {
+            Some([destination, source]) => match (operand(destination), operand(source)) {
+                (Some(Operand::Memory(destination)), Some(Operand::Memory(source))) => {
+                    Some(vec![at(source), fixed("D=M"), at(destination), fixed("M=D")])
+                }
+                (Some(Operand::D), Some(Operand::Memory(source))) => Some(vec![at(source), fixed("D=M")]),
+                (Some(Operand::Memory(destination)), Some(Operand::D)) => Some(vec![at(destination), fixed("M=D")]),
+                _ => None,
+            },
+            _ => None,
+        },
+        "inc" | "dec" | "clr" => {
+            let (register, memory) = match mnemonic {
+                "inc" => ("D=D+1", "M=M+1"),
+                "dec" => ("D=D-1", "M=M-1"),
+                _ => ("D=0", "M=0"),
+            };
+            match operand(operands) {
+                Some(Operand::D) => Some(vec![fixed(register)]),
+                Some(Operand::Memory(address)) => Some(vec![at(address), fixed(memory)]),
+                None => None,
+            }
+        }
+        "ld" => match comma_separated(operands).ok().as_deref() {
+            Some([[register], value]) if register.text == "D" => Some(vec![at(value), fixed("D=A")]),
+            Some([[register], value]) if register.text == "A" => Some(vec![at(value)]),
+            _ => None,
+        },
+        _ => None,
+    };
+    expanded.ok_or_else(|| {
+        AssembleError::new(ErrorKind::InvalidInstruction, spanned(line, tokens))
+            .at("", 0, column)
+            .with_related(Related::note(usage(mnemonic)))
+    })
+}
+
+// how a pseudo-instruction is written, for the note on a malformed one
+fn usage(mnemonic: &str) -> &'static str {
+    match mnemonic {
+        "goto" => "expected `goto LABEL`",
+        "if" => "expected `if D>0 goto LABEL`, the condition compares D with 0 using >, >=, <, <=, == or !=",
+        "mov" => "expected `mov DEST, SRC`, each one D or a RAM address such as `RAM[x]`",
+        "ld" => "expected `ld D, constant` or `ld A, constant`",
+        _ => "expected D or a RAM address such as `x` or `RAM[x]` after the mnemonic",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn expanded(line: &str) -> Result<Vec<String>, AssembleError> {
+        let tokens: Vec<Token> = Lexer::new(line).collect();
+        Ok(expand(&tokens, line)?.into_iter().map(|line| line.text).collect())
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(expanded("goto LOOP").unwrap(), vec!["@LOOP", "0;JMP"]);
+        assert_eq!(expanded("if D >= 0 goto END").unwrap(), vec!["@END", "D;JGE"]);
+        assert_eq!(expanded("if D!=0 goto END").unwrap(), vec!["@END", "D;JNE"]);
+        assert_eq!(expanded("mov RAM[x], RAM[SCREEN + 1]").unwrap(), vec!["@SCREEN + 1", "D=M", "@x", "M=D"]);
+        assert_eq!(expanded("mov D, y").unwrap(), vec!["@y", "D=M"]);
+        assert_eq!(expanded("mov RAM[R13], D").unwrap(), vec!["@R13", "M=D"]);
+        assert_eq!(expanded("inc i").unwrap(), vec!["@i", "M=M+1"]);
+        assert_eq!(expanded("dec D").unwrap(), vec!["D=D-1"]);
+        assert_eq!(expanded("clr RAM[sum]").unwrap(), vec!["@sum", "M=0"]);
+        assert_eq!(expanded("ld D, 0x4000").unwrap(), vec!["@0x4000", "D=A"]);
+        assert_eq!(expanded("ld A, 7").unwrap(), vec!["@7"]);
+        for malformed in ["goto", "if D>1 goto END", "if D>0 END", "mov D, D", "mov x", "inc", "ld M, 1", "ld D,"] {
+            let error = expanded(malformed).unwrap_err();
+            assert_eq!((error.kind, error.column), (ErrorKind::InvalidInstruction, 1), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_columns() {
+        let line = "  mov RAM[x], RAM[y]";
+        let tokens: Vec<Token> = Lexer::new(line).collect();
+        let lines = expand(&tokens, line).unwrap();
+        // `y` is at column 19 of the line, the `@` and the fixed instructions point at `mov`
+        assert_eq!((lines[0].original_column(1), lines[0].original_column(2)), (3, 19));
+        assert_eq!(lines[1].original_column(1), 3);
+        assert_eq!(lines[2].original_column(2), 11);
+    }
+}
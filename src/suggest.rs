@@ -0,0 +1,122 @@
+// finds the closest match to a misspelled mnemonic or symbol
+// uses the optimal string alignment distance: insertions, deletions, substitutions
+// and swapping two neighbouring characters (JPM -> JMP) all cost 1
+
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // distances[i][j] -> distance between the first i chars of a and the first j chars of b
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in distances[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+// returns the candidate closest to word, if any is close enough to be a likely typo
+// case is ignored when comparing, so `jmp` suggests `JMP`
+// a word of 3 chars or less has too little left after an edit to recognize, `X` is not a typo
+// of `0` nor `A+M` of `A+1`, it only gets a candidate with the same chars in another order
+// ties go to the candidate whose length is closest to word, so `DM` suggests `MD` rather than `M`,
+// then to the one that comes first
+pub fn closest<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let word = word.to_uppercase();
+    candidates
+        .into_iter()
+        .filter_map(|candidate| rank(&word, candidate).map(|rank| (rank, candidate)))
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, candidate)| candidate)
+}
+
+// closest for candidates in no particular order, such as the keys of a map,
+// the last ties go to the name that sorts first so the suggestion does not depend on hashing
+pub fn closest_unordered<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let word = word.to_uppercase();
+    candidates
+        .into_iter()
+        .filter_map(|candidate| rank(&word, candidate).map(|rank| (rank, candidate)))
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+// how close candidate is to the uppercased word, None if it is too far to suggest
+fn rank(word: &str, candidate: &str) -> Option<(usize, usize)> {
+    let length = word.chars().count();
+    let max_distance = if length > 3 { length / 3 } else { 0 };
+    // a candidate much longer or shorter can not be close, checked before uppercasing it
+    let length_difference = candidate.chars().count().abs_diff(length);
+    if length_difference > max_distance {
+        return None;
+    }
+    let distance = edit_distance(word, &candidate.to_uppercase());
+    (distance <= max_distance || (length <= 3 && is_anagram(word, candidate))).then_some((distance, length_difference))
+}
+
+// whether b has exactly the chars of a, ignoring case and order
+fn is_anagram(a: &str, b: &str) -> bool {
+    let sorted = |word: &str| {
+        let mut chars: Vec<char> = word.to_uppercase().chars().collect();
+        chars.sort_unstable();
+        chars
+    };
+    sorted(a) == sorted(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("JMP", "JMP"), 0);
+        assert_eq!(edit_distance("JMPP", "JMP"), 1);
+        assert_eq!(edit_distance("JPM", "JMP"), 1);
+        assert_eq!(edit_distance("LOOOP", "LOOP"), 1);
+        assert_eq!(edit_distance("", "END"), 3);
+        assert_eq!(edit_distance("D+X", "D|A"), 2);
+    }
+
+    #[test]
+    fn test_closest() {
+        let jumps = ["JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+        assert_eq!(closest("JMPP", jumps), Some("JMP"));
+        assert_eq!(closest("jmp", jumps), Some("JMP"));
+        assert_eq!(closest("LOOOP", ["END", "LOOP"]), Some("LOOP"));
+        assert_eq!(closest("XYZZY", jumps), None);
+        assert_eq!(closest("LOOPX", ["LOOPC", "LOOPA", "LOOPB"]), Some("LOOPC"));
+        assert_eq!(closest("JPM", jumps), Some("JMP"));
+    }
+
+    #[test]
+    fn test_closest_unordered() {
+        assert_eq!(closest_unordered("LOOPX", ["LOOPC", "LOOPA", "LOOPB"]), Some("LOOPA"));
+        assert_eq!(closest_unordered("LOOPX", ["LOOPC", "END", "LOOPS2"]), Some("LOOPC"));
+        assert_eq!(closest_unordered("XYZZY", ["LOOPC"]), None);
+    }
+
+    #[test]
+    fn test_closest_short_words() {
+        // every comp is within one edit of some other short comp
+        let comps = ["0", "1", "-1", "D", "A", "M", "D+1", "A+1", "M+1", "D+A", "D+M", "D|A"];
+        assert_eq!(closest("X", comps), None);
+        assert_eq!(closest("A+M", comps), None);
+        assert_eq!(closest("EN", ["END"]), None);
+        assert_eq!(closest("1+D", comps), Some("D+1"));
+        assert_eq!(closest("DM", ["M", "D", "MD", "AM"]), Some("MD"));
+    }
+}
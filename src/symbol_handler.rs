@@ -16,7 +16,19 @@
 // while reading the input, add labels and new variables to the table
 // whenever you see "@XXX" command, where XXX , consult the table to replace the XXX symbol with its address
 
-use std::collections::HashMap;
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    rc::Rc,
+};
+
+use crate::{
+    error::{AssembleError, ErrorKind, Related},
+    interner::Name,
+    suggest::closest_unordered,
+};
+
+// variables live in RAM[16..16384], the screen memory map starts right after them
+const VARIABLES_END: u16 = 16384;
 
 pub fn get_predefined_symbols() -> Vec<(String, u16)> {
     vec![
@@ -46,11 +58,23 @@ pub fn get_predefined_symbols() -> Vec<(String, u16)> {
     ]
 }
 // memory address go from 0 -> 32767 -> u16
+// keyed by the interned names of the instructions, adding a symbol never copies its name
+// and takes a single lookup
 pub struct SymbolTable {
-    symbol_table: HashMap<String, u16>,
+    symbol_table: HashMap<Name, (u16, SymbolKind)>,
     next_free_address: u16,
 }
 
+// how a symbol got into the table
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SymbolKind {
+    Predefined,
+    Label,
+    Variable,
+    // bound to a value with `.equ`, it takes no RAM or ROM
+    Constant,
+}
+
 impl SymbolTable {
     pub fn new() -> Self {
         let mut symbol_table = Self {
@@ -62,19 +86,64 @@ impl SymbolTable {
     }
     pub fn init(&mut self) {
         for symbol in get_predefined_symbols() {
-            self.symbol_table.insert(symbol.0, symbol.1);
+            self.symbol_table.insert(Name::from(symbol.0), (symbol.1, SymbolKind::Predefined));
         }
     }
-    // checks if symbol is in the table, if in table, ignore
+    // checks if symbol is in the table, if a variable is in table, ignore
     // else, add to table
-    pub fn add_symbol(&mut self, new_symbol: &str, memory_address: u16, is_variable: bool) {
-        if !self.contains_symbol(&new_symbol) {
-            self.symbol_table
-                .insert(new_symbol.to_string(), memory_address);
-            if is_variable {
-                // increment the free address if the symbol added is a variable
-                // since labels dont take up space in memory, they are ignored
-                self.increment_next_free_address();
+    // labels can only be defined once and can never replace a predefined symbol
+    pub fn add_symbol(
+        &mut self,
+        new_symbol: &Name,
+        memory_address: u16,
+        is_variable: bool,
+    ) -> Result<(), AssembleError> {
+        let entry = match self.symbol_table.entry(Rc::clone(new_symbol)) {
+            Entry::Vacant(entry) => entry,
+            Entry::Occupied(_) if is_variable => return Ok(()),
+            Entry::Occupied(entry) => {
+                return Err(match *entry.get() {
+                    (_, SymbolKind::Label) => AssembleError::new(ErrorKind::DuplicateLabel, new_symbol),
+                    (_, SymbolKind::Constant) => AssembleError::new(ErrorKind::DuplicateSymbol, new_symbol),
+                    (address, _) => AssembleError::new(ErrorKind::PredefinedLabel, new_symbol).with_related(
+                        Related::note(&format!("`{}` is predefined as address {}", new_symbol, address)),
+                    ),
+                });
+            }
+        };
+        if is_variable {
+            // increment the free address if the symbol added is a variable
+            // since labels dont take up space in memory, they are ignored
+            // fails once the variable area is exhausted, a variable in the screen or keyboard
+            // memory map would draw on the screen, and past 32767 `@address` is not an A-instruction
+            if self.next_free_address >= VARIABLES_END {
+                return Err(AssembleError::new(ErrorKind::OutOfMemory, new_symbol));
+            }
+            self.next_free_address += 1;
+            entry.insert((memory_address, SymbolKind::Variable));
+        } else {
+            entry.insert((memory_address, SymbolKind::Label));
+        }
+        Ok(())
+    }
+
+    // binds name to value without allocating anything
+    // constants can not share a name with a label, another constant or a predefined symbol
+    pub fn add_constant(&mut self, name: &Name, value: u16) -> Result<(), AssembleError> {
+        match self.symbol_table.entry(Rc::clone(name)) {
+            Entry::Vacant(entry) => {
+                entry.insert((value, SymbolKind::Constant));
+                Ok(())
+            }
+            Entry::Occupied(entry) => {
+                let error = AssembleError::new(ErrorKind::DuplicateSymbol, name);
+                match *entry.get() {
+                    (address, SymbolKind::Predefined) => Err(error.with_related(Related::note(&format!(
+                        "`{}` is predefined as address {}",
+                        name, address
+                    )))),
+                    _ => Err(error),
+                }
             }
         }
     }
@@ -85,37 +154,60 @@ impl SymbolTable {
 
     // checks if symbol is in the table
     // returns mem address
-    pub fn get_memory_address(&self, symbol: &str) -> u16 {
-        if let Some(value) = self.symbol_table.get(symbol) {
-            return *value;
-        } else {
-            panic!("symbol not registered in symbol table");
-        }
+    pub fn get_memory_address(&self, symbol: &str) -> Result<u16, AssembleError> {
+        self.symbol_table
+            .get(symbol)
+            .map(|&(address, _)| address)
+            .ok_or_else(|| {
+                AssembleError::new(ErrorKind::UnknownSymbol, symbol)
+                    .with_suggestion(self.closest_symbol(symbol))
+            })
     }
 
-    pub fn get_next_free_address(&self) -> u16 {
-        self.next_free_address
+    // the known symbol that symbol is most likely a typo of
+    pub fn closest_symbol(&self, symbol: &str) -> Option<&str> {
+        closest_unordered(symbol, self.symbol_table.keys().map(|name| &**name))
     }
 
-    // increments the next free address variable
-    fn increment_next_free_address(&mut self) {
-        if self.next_free_address + 1 > u16::MAX {
-            panic!(
-                "Spurious dragon Error: cannot exceed {} variables",
-                u16::MAX
-            );
-        } else if self.next_free_address + 1 == 16384 {
-            self.next_free_address += 2; // skip memory slot 16384
-        } else if self.next_free_address + 1 == 24576 {
-            self.next_free_address += 2; // skip memory slot 24576
-        } else {
-            self.next_free_address += 1;
-        }
+    // the label that symbol is most likely a typo of
+    pub fn closest_label(&self, symbol: &str) -> Option<&str> {
+        closest_unordered(symbol, self.names(SymbolKind::Label))
+    }
+
+    // every label with the ROM address it names, sorted by name
+    pub fn labels(&self) -> BTreeMap<String, u16> {
+        self.addresses(SymbolKind::Label)
+    }
+
+    // every constant with its value, sorted by name
+    pub fn constants(&self) -> BTreeMap<String, u16> {
+        self.addresses(SymbolKind::Constant)
+    }
+
+    // every variable with the RAM address it was given, sorted by name
+    pub fn variables(&self) -> BTreeMap<String, u16> {
+        self.addresses(SymbolKind::Variable)
+    }
+
+    fn names(&self, kind: SymbolKind) -> impl Iterator<Item = &str> {
+        self.symbol_table
+            .iter()
+            .filter(move |(_, (_, symbol_kind))| *symbol_kind == kind)
+            .map(|(name, _)| &**name)
+    }
+
+    fn addresses(&self, kind: SymbolKind) -> BTreeMap<String, u16> {
+        self.names(kind)
+            .map(|name| (name.to_string(), self.symbol_table[name].0))
+            .collect()
+    }
+
+    pub fn get_next_free_address(&self) -> u16 {
+        self.next_free_address
     }
 }
 
 #[cfg(test)]
-
 mod tests {
     use super::*;
 
@@ -126,17 +218,104 @@ mod tests {
     #[test]
     fn test_init() {
         let symbol_table = init_symbol_table();
-        assert_eq!(symbol_table.contains_symbol("R0"), true);
-        assert_eq!(symbol_table.contains_symbol("R1"), true);
-        assert_eq!(symbol_table.contains_symbol("SCREEN"), true);
+        assert!(symbol_table.contains_symbol("R0"));
+        assert!(symbol_table.contains_symbol("R1"));
+        assert!(symbol_table.contains_symbol("SCREEN"));
     }
 
     #[test]
     fn test_add_symbol() {
         let mut symbol_table = init_symbol_table();
-        assert_eq!(symbol_table.contains_symbol("n"), false);
-        symbol_table.add_symbol("n", 16, true);
-        assert_eq!(symbol_table.contains_symbol("n"), true);
-        assert_eq!(symbol_table.get_memory_address("n"), 16);
+        assert!(!symbol_table.contains_symbol("n"));
+        symbol_table.add_symbol(&"n".into(), 16, true).unwrap();
+        assert!(symbol_table.contains_symbol("n"));
+        assert_eq!(symbol_table.get_memory_address("n").unwrap(), 16);
+    }
+
+    #[test]
+    fn test_labels_and_variables() {
+        let mut symbol_table = init_symbol_table();
+        symbol_table.add_symbol(&"LOOP".into(), 4, false).unwrap();
+        symbol_table.add_symbol(&"n".into(), 16, true).unwrap();
+        symbol_table.add_symbol(&"SCREEN".into(), 17, true).unwrap();
+        assert_eq!(symbol_table.labels(), BTreeMap::from([("LOOP".to_string(), 4)]));
+        assert_eq!(symbol_table.variables(), BTreeMap::from([("n".to_string(), 16)]));
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut symbol_table = init_symbol_table();
+        symbol_table.add_constant(&"ROWS".into(), 256).unwrap();
+        assert_eq!(symbol_table.get_memory_address("ROWS").unwrap(), 256);
+        // a variable reference to a constant is a lookup, nothing is allocated
+        symbol_table.add_symbol(&"ROWS".into(), 16, true).unwrap();
+        assert_eq!(symbol_table.get_next_free_address(), 16);
+        assert_eq!(symbol_table.add_constant(&"ROWS".into(), 1).unwrap_err().kind, ErrorKind::DuplicateSymbol);
+        assert_eq!(symbol_table.add_symbol(&"ROWS".into(), 4, false).unwrap_err().kind, ErrorKind::DuplicateSymbol);
+        symbol_table.add_symbol(&"LOOP".into(), 4, false).unwrap();
+        assert_eq!(symbol_table.add_constant(&"LOOP".into(), 1).unwrap_err().kind, ErrorKind::DuplicateSymbol);
+        let error = symbol_table.add_constant(&"KBD".into(), 1).unwrap_err();
+        assert_eq!(error.related.unwrap().message, "`KBD` is predefined as address 24576");
+        assert_eq!(symbol_table.constants(), BTreeMap::from([("ROWS".to_string(), 256)]));
+        assert!(symbol_table.variables().is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_label() {
+        let mut symbol_table = init_symbol_table();
+        symbol_table.add_symbol(&"LOOP".into(), 4, false).unwrap();
+        let error = symbol_table.add_symbol(&"LOOP".into(), 9, false).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::DuplicateLabel);
+        assert_eq!(symbol_table.get_memory_address("LOOP").unwrap(), 4);
+    }
+
+    #[test]
+    fn test_label_redefines_predefined_symbol() {
+        let mut symbol_table = init_symbol_table();
+        let error = symbol_table.add_symbol(&"SCREEN".into(), 4, false).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::PredefinedLabel);
+        assert_eq!(error.related.unwrap().message, "`SCREEN` is predefined as address 16384");
+        // variables referencing a known symbol are still just lookups
+        symbol_table.add_symbol(&"SCREEN".into(), 16, true).unwrap();
+        assert_eq!(symbol_table.get_memory_address("SCREEN").unwrap(), 16384);
+    }
+
+    #[test]
+    fn test_unknown_symbol() {
+        let symbol_table = init_symbol_table();
+        let error = symbol_table.get_memory_address("LOOP").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnknownSymbol);
+        assert_eq!(error.text, "LOOP");
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let mut symbol_table = init_symbol_table();
+        symbol_table.add_symbol(&"LOOP".into(), 4, false).unwrap();
+        symbol_table.add_symbol(&"counter".into(), 16, true).unwrap();
+        assert_eq!(symbol_table.closest_label("LOOOP"), Some("LOOP"));
+        assert_eq!(symbol_table.closest_label("countr"), None);
+        assert_eq!(symbol_table.closest_symbol("countr"), Some("counter"));
+        assert_eq!(
+            symbol_table.get_memory_address("SCREN").unwrap_err().suggestion.as_deref(),
+            Some("SCREEN")
+        );
+        // equally close labels always suggest the same one
+        for label in ["LOOPD", "LOOPB", "LOOPC", "LOOPA"] {
+            symbol_table.add_symbol(&label.into(), 8, false).unwrap();
+        }
+        assert_eq!(symbol_table.closest_label("LOOPX"), Some("LOOPA"));
+    }
+
+    #[test]
+    fn test_out_of_memory() {
+        let mut symbol_table = init_symbol_table();
+        symbol_table.next_free_address = 16383;
+        // the last address below the screen is still free
+        symbol_table.add_symbol(&"last".into(), 16383, true).unwrap();
+        let error = symbol_table.add_symbol(&"n".into(), 16384, true).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::OutOfMemory);
+        assert!(!symbol_table.contains_symbol("n"));
+        assert_eq!(symbol_table.variables()["last"], 16383);
     }
 }